@@ -1,7 +1,8 @@
 use anyhow::*;
 use glob::glob;
+use image::RgbaImage;
 use std::fs::{read_to_string, write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 struct ShaderData {
     src: String,
@@ -36,7 +37,7 @@ impl ShaderData {
     }
 }
 
-fn main() -> Result<()> {
+fn compile_shaders() -> Result<()> {
     // Collect all shaders recursively within /src/
     let mut shader_paths = [
         glob("./src/**/*.vert")?,
@@ -79,3 +80,113 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+// Empty border around each tile in the atlas, filled by repeating the tile's edge pixels.
+// Keeps linear filtering from bleeding in colours from the neighbouring tiles.
+const TILE_PADDING: u32 = 4;
+
+// Pack every src/tiles/*.png into one atlas. All tiles must have the same size.
+// Writes blockatlas.png, atlas_tiles.rs (included by atlas.rs) and atlas_tiles.json to OUT_DIR.
+fn pack_atlas() -> Result<()> {
+    println!("cargo:rerun-if-changed=src/tiles");
+
+    let mut tiles = Vec::new();
+    for path in glob("./src/tiles/*.png")? {
+        let path = path?;
+        println!("cargo:rerun-if-changed={}", path.display());
+        let name = path
+            .file_stem()
+            .context("Tile has no file name")?
+            .to_str()
+            .context("Tile name cannot be converted to &str")?
+            .to_string();
+        let img = image::open(&path)
+            .with_context(|| format!("Unable to read tile {}", path.display()))?
+            .to_rgba8();
+        tiles.push((name, img));
+    }
+    tiles.sort_by(|a, b| a.0.cmp(&b.0));
+    if tiles.is_empty() {
+        bail!("No tiles found in src/tiles");
+    }
+
+    let (tile_width, tile_height) = tiles[0].1.dimensions();
+    for (name, img) in &tiles {
+        if img.dimensions() != (tile_width, tile_height) {
+            bail!(
+                "Tile {} is {}x{}, expected {}x{} like the others",
+                name,
+                img.width(),
+                img.height(),
+                tile_width,
+                tile_height
+            );
+        }
+    }
+
+    let cell_width = tile_width + 2 * TILE_PADDING;
+    let cell_height = tile_height + 2 * TILE_PADDING;
+    let columns = (tiles.len() as f32).sqrt().ceil() as u32;
+    let rows = (tiles.len() as u32 + columns - 1) / columns;
+    let (width, height) = (columns * cell_width, rows * cell_height);
+
+    let mut atlas = RgbaImage::new(width, height);
+    let mut manifest = Vec::new();
+    for (i, (name, img)) in tiles.iter().enumerate() {
+        let x0 = (i as u32 % columns) * cell_width;
+        let y0 = (i as u32 / columns) * cell_height;
+        for y in 0..cell_height {
+            for x in 0..cell_width {
+                // Clamp into the tile, so the padding repeats the edge
+                let sx = (x as i64 - TILE_PADDING as i64).max(0).min(tile_width as i64 - 1) as u32;
+                let sy = (y as i64 - TILE_PADDING as i64).max(0).min(tile_height as i64 - 1) as u32;
+                atlas.put_pixel(x0 + x, y0 + y, *img.get_pixel(sx, sy));
+            }
+        }
+        let rect = [
+            (x0 + TILE_PADDING) as f32 / width as f32,
+            (y0 + TILE_PADDING) as f32 / height as f32,
+            tile_width as f32 / width as f32,
+            tile_height as f32 / height as f32,
+        ];
+        manifest.push((name, rect));
+    }
+
+    let out_dir = PathBuf::from(std::env::var("OUT_DIR")?);
+    atlas.save(out_dir.join("blockatlas.png"))?;
+    write_manifest(&out_dir, &manifest, width, height)?;
+
+    Ok(())
+}
+
+// atlas_tiles.rs for the game, atlas_tiles.json (which also has the image size) for tooling
+fn write_manifest(out_dir: &Path, manifest: &[(&String, [f32; 4])], width: u32, height: u32) -> Result<()> {
+    let mut rust = String::from("// Generated by build.rs from src/tiles. Do not edit.\n");
+    rust += "// Tile name (file name without .png), then u, v of top left corner, width and height\n";
+    rust += "pub const ATLAS_TILES: &[(&str, [f32; 4])] = &[\n";
+    for (name, r) in manifest {
+        rust += &format!("    ({:?}, [{:?}, {:?}, {:?}, {:?}]),\n", name, r[0], r[1], r[2], r[3]);
+    }
+    rust += "];\n";
+    write(out_dir.join("atlas_tiles.rs"), rust)?;
+
+    let tiles = manifest
+        .iter()
+        .map(|(name, r)| format!("    {:?}: [{}, {}, {}, {}]", name, r[0], r[1], r[2], r[3]))
+        .collect::<Vec<_>>()
+        .join(",\n");
+    let json = format!(
+        "{{\n  \"width\": {},\n  \"height\": {},\n  \"tiles\": {{\n{}\n  }}\n}}\n",
+        width,
+        height,
+        tiles
+    );
+    write(out_dir.join("atlas_tiles.json"), json)?;
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    compile_shaders()?;
+    pack_atlas()?;
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use anyhow::*;
+use cgmath::Vector4;
+use std::collections::HashMap;
+
+use crate::mesher::{Face, FACES};
+use crate::registry::{BlockRegistry, BlockType};
+
+//ATLAS_TILES, packed by build.rs from src/tiles/*.png
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/atlas_tiles.rs"));
+}
+
+//The packed atlas image
+pub const ATLAS_PNG: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/blockatlas.png"));
+
+//Named tiles in one texture. UV rectangles are looked up once, meshers only deal with tile indices.
+#[derive(Debug, Clone)]
+pub struct TextureAtlas {
+    names: Vec<String>,
+    //u, v of top left corner, then width and height
+    rects: Vec<Vector4<f32>>,
+    by_name: HashMap<String, usize>,
+    //What uses each tile (e.g. "grass top")
+    users: Vec<Vec<String>>,
+    //Tile index for each face of each block, indexed by BlockType and then face as ordered in FACES
+    block_faces: Vec<[usize; 6]>,
+}
+
+impl TextureAtlas {
+    pub fn new(tiles: &[(&str, [f32; 4])]) -> Result<Self> {
+        if tiles.is_empty() {
+            bail!("Texture atlas has no tiles");
+        }
+        let mut atlas = Self {
+            names: Vec::new(),
+            rects: Vec::new(),
+            by_name: HashMap::new(),
+            users: Vec::new(),
+            block_faces: Vec::new(),
+        };
+        for (name, r) in tiles {
+            if atlas.by_name.contains_key(*name) {
+                bail!("Tile '{}' is defined twice", name);
+            }
+            if r.iter().any(|c| !(0.0..=1.0).contains(c)) || r[0] + r[2] > 1.0 || r[1] + r[3] > 1.0 {
+                bail!("Tile '{}' has UV rectangle {:?} outside the texture", name, r);
+            }
+            atlas.by_name.insert(String::from(*name), atlas.names.len());
+            atlas.names.push(String::from(*name));
+            atlas.rects.push(Vector4::new(r[0], r[1], r[2], r[3]));
+            atlas.users.push(Vec::new());
+        }
+        Ok(atlas)
+    }
+
+    //The atlas packed by build.rs, checked against the registry
+    pub fn builtin(registry: &BlockRegistry) -> Result<Self> {
+        Self::for_registry(generated::ATLAS_TILES, registry)
+    }
+
+    //Atlas with the tile of every block face resolved. Fails if a block refers to a missing tile.
+    pub fn for_registry(tiles: &[(&str, [f32; 4])], registry: &BlockRegistry) -> Result<Self> {
+        let mut atlas = Self::new(tiles)?;
+        atlas.block_faces = vec![[0; 6]; registry.len()];
+        for (blocktype, def) in registry.iter() {
+            for (i, face) in FACES.iter().enumerate() {
+                let tile = def.tile(*face);
+                let index = atlas.index(tile).with_context(|| {
+                    format!("Block '{}' uses tile '{}', which is not in the atlas", def.name, tile)
+                })?;
+                atlas.users[index].push(format!("{} {:?}", def.name, face).to_lowercase());
+                atlas.block_faces[blocktype.0 as usize][i] = index;
+            }
+        }
+        Ok(atlas)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.by_name.get(name).copied()
+    }
+
+    //All tiles in the atlas, with their UV rectangle and what uses them
+    pub fn defined_tiles(&self) -> impl Iterator<Item = (&str, Vector4<f32>, &[String])> {
+        self.names
+            .iter()
+            .zip(self.rects.iter())
+            .zip(self.users.iter())
+            .map(|((name, rect), users)| (name.as_str(), *rect, users.as_slice()))
+    }
+
+    //UV rectangle, as used in ModelVertex::tile
+    pub fn rect(&self, index: usize) -> Vector4<f32> {
+        self.rects[index]
+    }
+
+    //Tile index of a block face. Blocks unknown to the registry get the first tile.
+    pub fn face_tile(&self, blocktype: BlockType, face: Face) -> usize {
+        let i = FACES.iter().position(|f| *f == face).unwrap();
+        self.block_faces
+            .get(blocktype.0 as usize)
+            .map(|faces| faces[i])
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //A 4x4 grid of tiles named by row and column, e.g. "r2c1"
+    fn grid_tiles() -> Vec<(String, [f32; 4])> {
+        let mut tiles = Vec::new();
+        for row in 0..4 {
+            for column in 0..4 {
+                let rect = [column as f32 / 4.0, row as f32 / 4.0, 0.25, 0.25];
+                tiles.push((format!("r{}c{}", row, column), rect));
+            }
+        }
+        tiles
+    }
+
+    fn atlas(tiles: &[(String, [f32; 4])]) -> Result<TextureAtlas> {
+        let tiles: Vec<(&str, [f32; 4])> = tiles.iter().map(|(name, rect)| (name.as_str(), *rect)).collect();
+        TextureAtlas::new(&tiles)
+    }
+
+    #[test]
+    fn rect_of_a_row_and_column() {
+        let atlas = atlas(&grid_tiles()).unwrap();
+        assert_eq!(atlas.len(), 16);
+        let index = atlas.index("r2c1").unwrap();
+        assert_eq!(atlas.rect(index), Vector4::new(0.25, 0.5, 0.25, 0.25));
+        assert_eq!(atlas.index("r4c0"), None);
+    }
+
+    #[test]
+    fn tile_outside_the_grid_is_an_error() {
+        let mut tiles = grid_tiles();
+        tiles.push((String::from("r4c0"), [0.0, 1.0, 0.25, 0.25]));
+        assert!(atlas(&tiles).is_err());
+        let mut tiles = grid_tiles();
+        tiles.push((String::from("wide"), [0.75, 0.0, 0.5, 0.25]));
+        assert!(atlas(&tiles).is_err());
+    }
+
+    #[test]
+    fn unknown_tile_name_is_an_error() {
+        let registry = BlockRegistry::builtin().unwrap();
+        let tiles: Vec<(&str, [f32; 4])> = generated::ATLAS_TILES
+            .iter()
+            .filter(|(name, _)| *name != "stone")
+            .cloned()
+            .collect();
+        let e = TextureAtlas::for_registry(&tiles, &registry).unwrap_err();
+        assert!(format!("{:?}", e).contains("'stone'"), "{:?}", e);
+        assert!(TextureAtlas::for_registry(generated::ATLAS_TILES, &registry).is_ok());
+    }
+
+    #[test]
+    fn defined_tiles_lists_every_tile_and_its_users() {
+        let registry = BlockRegistry::builtin().unwrap();
+        let atlas = TextureAtlas::builtin(&registry).unwrap();
+        let tiles: Vec<_> = atlas.defined_tiles().collect();
+        assert_eq!(tiles.len(), atlas.len());
+        let (_, rect, users) = tiles.iter().find(|(name, _, _)| *name == "grass_top").unwrap();
+        assert_eq!(*rect, atlas.rect(atlas.index("grass_top").unwrap()));
+        assert_eq!(users.to_vec(), vec![String::from("grass top")]);
+    }
+}
@@ -32,10 +32,23 @@ impl Camera {
         }
     }
 
+    pub fn yaw(&self) -> Rad<f32> {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> Rad<f32> {
+        self.pitch
+    }
+
+    //Unit vector the camera looks along, e.g. for World::raycast
+    pub fn direction(&self) -> Vector3<f32> {
+        Vector3::new(self.yaw.0.cos(), self.pitch.0.sin(), self.yaw.0.sin()).normalize()
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         Matrix4::look_at_dir(
             self.position,
-            Vector3::new(self.yaw.0.cos(), self.pitch.0.sin(), self.yaw.0.sin()).normalize(),
+            self.direction(),
             Vector3::unit_y(),
         )
     }
@@ -147,15 +160,13 @@ impl CameraController {
         };
     }
 
+    //Free flying, through blocks
     pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
         let dt = dt.as_secs_f32();
 
         // Move forward/backward and left/right
+        camera.position += self.walk_velocity(camera) * dt;
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
-        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
-        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
         // Move in/out (aka. "zoom")
         // Note: this isn't an actual zoom. The camera's position
@@ -171,6 +182,28 @@ impl CameraController {
         // modify the y coordinate directly.
         camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
 
+        self.rotate_camera(camera, dt);
+    }
+
+    //Horizontal velocity from the movement keys, along the way the camera faces
+    pub fn walk_velocity(&self, camera: &Camera) -> Vector3<f32> {
+        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
+        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin);
+        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos);
+        (forward * (self.amount_forward - self.amount_backward) + right * (self.amount_right - self.amount_left))
+            * self.speed
+    }
+
+    //Space is held. Jumps when walking.
+    pub fn jumping(&self) -> bool {
+        self.amount_up > 0.0
+    }
+
+    //Only look around, for when something else moves the camera
+    pub fn rotate_camera(&mut self, camera: &mut Camera, dt: f32) {
+        // Scrolling moves the camera, which only makes sense when flying
+        self.scroll = 0.0;
+
         // Rotate
         camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
         camera.pitch += Rad(-self.rotate_vertical) * self.sensitivity * dt;
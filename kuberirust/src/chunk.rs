@@ -0,0 +1,306 @@
+use crate::registry::BlockType;
+
+//Storage for the voxels of one chunk. Index of local (x,y,z) is x + size*(y + size*z).
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkStorage {
+    //One entry per voxel
+    Dense(Vec<BlockType>),
+    //Each voxel stores a few bits indexing into palette. Good for chunks with few block types.
+    Palette(PaletteStorage),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteStorage {
+    palette: Vec<BlockType>,
+    bits: u32,
+    words: Vec<u64>,
+}
+
+//Above this many block types palette storage is no longer smaller than dense storage
+const MAX_PALETTE_BITS: u32 = 8;
+
+impl PaletteStorage {
+    fn new(volume: usize, fill: BlockType) -> Self {
+        let bits = 1;
+        Self {
+            palette: vec![fill],
+            bits,
+            words: vec![0; Self::word_count(volume, bits)],
+        }
+    }
+
+    //Smallest bit width (1, 2, 4 or 8) that can index a palette of the given length
+    fn bits_for(palette_len: usize) -> u32 {
+        let mut bits = 1;
+        while (1usize << bits) < palette_len {
+            bits *= 2;
+        }
+        bits
+    }
+
+    //Entries never straddle two words, since bits always divides 64
+    fn word_count(volume: usize, bits: u32) -> usize {
+        let per_word = (64 / bits) as usize;
+        (volume + per_word - 1) / per_word
+    }
+
+    fn get_index(&self, i: usize) -> usize {
+        let per_word = (64 / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        ((self.words[i / per_word] >> shift) & mask) as usize
+    }
+
+    fn set_index(&mut self, i: usize, value: usize) {
+        let per_word = (64 / self.bits) as usize;
+        let shift = (i % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !(mask << shift)) | ((value as u64 & mask) << shift);
+    }
+
+    fn get(&self, i: usize) -> BlockType {
+        self.palette[self.get_index(i)]
+    }
+
+    //Returns false if blocktype doesn't fit in the palette. Caller must then switch to dense storage.
+    fn set(&mut self, i: usize, volume: usize, blocktype: BlockType) -> bool {
+        let palette_index = match self.palette.iter().position(|b| *b == blocktype) {
+            Some(p) => p,
+            None => {
+                let bits = Self::bits_for(self.palette.len() + 1);
+                if bits > MAX_PALETTE_BITS {
+                    return false;
+                }
+                if bits != self.bits {
+                    self.repack(volume, bits);
+                }
+                self.palette.push(blocktype);
+                self.palette.len() - 1
+            }
+        };
+        self.set_index(i, palette_index);
+        true
+    }
+
+    fn repack(&mut self, volume: usize, bits: u32) {
+        let old = std::mem::replace(
+            self,
+            Self {
+                palette: Vec::new(),
+                bits,
+                words: vec![0; Self::word_count(volume, bits)],
+            },
+        );
+        for i in 0..volume {
+            self.set_index(i, old.get_index(i));
+        }
+        self.palette = old.palette;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    size: i32,
+    storage: ChunkStorage,
+    //Sky light in the high 4 bits and block light in the low 4 bits of each voxel, same index as
+    //the blocks. Not saved, lighting.rs fills it in when the chunk is loaded.
+    light: Vec<u8>,
+}
+
+impl Chunk {
+    //New chunk filled with air, stored dense
+    pub fn new(size: i32) -> Self {
+        let volume = (size * size * size) as usize;
+        Self {
+            size,
+            storage: ChunkStorage::Dense(vec![BlockType::AIR; volume]),
+            light: vec![0; volume],
+        }
+    }
+
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    pub fn volume(&self) -> usize {
+        (self.size * self.size * self.size) as usize
+    }
+
+    pub fn contains(&self, local: [i32; 3]) -> bool {
+        local.iter().all(|c| *c >= 0 && *c < self.size)
+    }
+
+    pub fn index(&self, local: [i32; 3]) -> usize {
+        debug_assert!(self.contains(local), "{:?} outside chunk", local);
+        (local[0] + self.size * (local[1] + self.size * local[2])) as usize
+    }
+
+    pub fn local_from_index(&self, i: usize) -> [i32; 3] {
+        let i = i as i32;
+        [i % self.size, (i / self.size) % self.size, i / (self.size * self.size)]
+    }
+
+    pub fn get(&self, local: [i32; 3]) -> BlockType {
+        self.get_at(self.index(local))
+    }
+
+    pub fn get_at(&self, i: usize) -> BlockType {
+        match &self.storage {
+            ChunkStorage::Dense(blocks) => blocks[i],
+            ChunkStorage::Palette(p) => p.get(i),
+        }
+    }
+
+    pub fn set(&mut self, local: [i32; 3], blocktype: BlockType) {
+        let i = self.index(local);
+        let volume = self.volume();
+        let fits = match &mut self.storage {
+            ChunkStorage::Dense(blocks) => {
+                blocks[i] = blocktype;
+                true
+            }
+            ChunkStorage::Palette(p) => p.set(i, volume, blocktype),
+        };
+        if !fits {
+            self.decompress();
+            self.set(local, blocktype);
+        }
+    }
+
+    pub fn sky_light(&self, local: [i32; 3]) -> u8 {
+        self.light[self.index(local)] >> 4
+    }
+
+    pub fn block_light(&self, local: [i32; 3]) -> u8 {
+        self.light[self.index(local)] & 0xF
+    }
+
+    pub fn set_sky_light(&mut self, local: [i32; 3], level: u8) {
+        let i = self.index(local);
+        self.light[i] = (self.light[i] & 0xF) | (level.min(15) << 4);
+    }
+
+    pub fn set_block_light(&mut self, local: [i32; 3], level: u8) {
+        let i = self.index(local);
+        self.light[i] = (self.light[i] & 0xF0) | level.min(15);
+    }
+
+    //All voxels, including air, with their local coordinates
+    pub fn iter(&self) -> impl Iterator<Item = ([i32; 3], BlockType)> + '_ {
+        (0..self.volume()).map(move |i| (self.local_from_index(i), self.get_at(i)))
+    }
+
+    //Non-air voxels only
+    pub fn iter_blocks(&self) -> impl Iterator<Item = ([i32; 3], BlockType)> + '_ {
+        self.iter().filter(|(_, b)| *b != BlockType::AIR)
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.storage, ChunkStorage::Palette(_))
+    }
+
+    //Switch to palette storage if the chunk has few enough block types. Returns true if compressed.
+    pub fn compress(&mut self) -> bool {
+        if self.is_compressed() {
+            return true;
+        }
+        let volume = self.volume();
+        let mut palette = PaletteStorage::new(volume, self.get_at(0));
+        for i in 0..volume {
+            if !palette.set(i, volume, self.get_at(i)) {
+                return false;
+            }
+        }
+        self.storage = ChunkStorage::Palette(palette);
+        true
+    }
+
+    pub fn decompress(&mut self) {
+        if let ChunkStorage::Palette(p) = &self.storage {
+            let blocks = (0..self.volume()).map(|i| p.get(i)).collect();
+            self.storage = ChunkStorage::Dense(blocks);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: i32 = 16;
+
+    //A dense chunk with the given number of different block types, and the block at each index
+    fn chunk_with_types(types: u16) -> (Chunk, Vec<BlockType>) {
+        let mut chunk = Chunk::new(SIZE);
+        let blocks: Vec<BlockType> = (0..chunk.volume())
+            .map(|i| BlockType((i * 7 % types as usize) as u16))
+            .collect();
+        for (i, blocktype) in blocks.iter().enumerate() {
+            chunk.set(chunk.local_from_index(i), *blocktype);
+        }
+        (chunk, blocks)
+    }
+
+    fn palette_bits(chunk: &Chunk) -> Option<u32> {
+        match &chunk.storage {
+            ChunkStorage::Palette(p) => Some(p.bits),
+            ChunkStorage::Dense(_) => None,
+        }
+    }
+
+    #[test]
+    fn palette_bits_grow_with_block_types() {
+        for (types, bits) in [(2, 1), (3, 2), (4, 2), (5, 4), (16, 4), (17, 8), (256, 8)].iter() {
+            let (mut chunk, blocks) = chunk_with_types(*types);
+            assert!(chunk.compress(), "{} types", types);
+            assert_eq!(palette_bits(&chunk), Some(*bits), "{} types", types);
+            for (i, blocktype) in blocks.iter().enumerate() {
+                assert_eq!(chunk.get_at(i), *blocktype);
+            }
+        }
+    }
+
+    #[test]
+    fn palette_repacks_when_blocks_are_added() {
+        let (mut chunk, mut blocks) = chunk_with_types(2);
+        assert!(chunk.compress());
+        for t in 2..200u16 {
+            let i = t as usize * 13;
+            chunk.set(chunk.local_from_index(i), BlockType(t));
+            blocks[i] = BlockType(t);
+        }
+        assert_eq!(palette_bits(&chunk), Some(8));
+        for (i, blocktype) in blocks.iter().enumerate() {
+            assert_eq!(chunk.get_at(i), *blocktype);
+        }
+    }
+
+    #[test]
+    fn too_many_block_types_fall_back_to_dense() {
+        let (mut chunk, _) = chunk_with_types(257);
+        assert!(!chunk.compress());
+        assert!(!chunk.is_compressed());
+
+        let (mut chunk, mut blocks) = chunk_with_types(256);
+        assert!(chunk.compress());
+        let local = [1, 2, 3];
+        chunk.set(local, BlockType(1000));
+        blocks[chunk.index(local)] = BlockType(1000);
+        assert!(!chunk.is_compressed());
+        for (i, blocktype) in blocks.iter().enumerate() {
+            assert_eq!(chunk.get_at(i), *blocktype);
+        }
+    }
+
+    #[test]
+    fn decompress_keeps_blocks() {
+        let (mut chunk, blocks) = chunk_with_types(5);
+        assert!(chunk.compress());
+        chunk.decompress();
+        assert!(!chunk.is_compressed());
+        for (i, blocktype) in blocks.iter().enumerate() {
+            assert_eq!(chunk.get_at(i), *blocktype);
+        }
+    }
+}
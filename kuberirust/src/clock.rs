@@ -0,0 +1,177 @@
+use cgmath::prelude::*;
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+//Game seconds per real second, a day lasts 20 minutes. Change with --time-speed.
+pub const DEFAULT_TIME_SPEED: f32 = 72.0;
+//Hours, mid morning. Change with --time.
+pub const DEFAULT_START_TIME: f32 = 10.0;
+
+const NIGHT_SKY: [f32; 3] = [0.005, 0.007, 0.02];
+const TWILIGHT_SKY: [f32; 3] = [0.5, 0.22, 0.12];
+const SUN_COLOR: [f32; 3] = [1.0, 0.97, 0.9];
+const TWILIGHT_SUN_COLOR: [f32; 3] = [1.0, 0.6, 0.35];
+const MOON_COLOR: [f32; 3] = [0.6, 0.7, 1.0];
+//The sky straight up is this much darker than at the horizon
+const ZENITH_SHADE: f32 = 0.5;
+//How much of the sky light is left at night
+const NIGHT_DAYLIGHT: f32 = 0.2;
+
+//Hours of the day where the sky changes. Between them everything is blended.
+const DAWN: f32 = 6.0;
+const DUSK: f32 = 18.0;
+//Twilight lasts this long on each side of dawn and dusk
+const TWILIGHT: f32 = 1.0;
+
+//Time of day, in hours from 0 to 24. Saved in world.ron. The sun rises in +x at DAWN, is overhead at noon and sets in -x at DUSK.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WorldClock {
+    pub time: f32,
+    //Game seconds per real second, 0 stops the clock
+    pub speed: f32,
+}
+
+impl Default for WorldClock {
+    fn default() -> Self {
+        Self::new(DEFAULT_START_TIME, DEFAULT_TIME_SPEED)
+    }
+}
+
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+//0 before edge - TWILIGHT, 1 after edge + TWILIGHT
+fn ramp(time: f32, edge: f32) -> f32 {
+    ((time - edge + TWILIGHT) / (2.0 * TWILIGHT)).clamp(0.0, 1.0)
+}
+
+impl WorldClock {
+    pub fn new(time: f32, speed: f32) -> Self {
+        Self {
+            time: time.rem_euclid(24.0),
+            speed,
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time = (self.time + dt * self.speed / 3600.0).rem_euclid(24.0);
+    }
+
+    //0 at night, 1 during the day, in between around dawn and dusk
+    fn day(&self) -> f32 {
+        ramp(self.time, DAWN) - ramp(self.time, DUSK)
+    }
+
+    //1 in the middle of dawn and dusk, 0 in full day or night
+    fn twilight(&self) -> f32 {
+        let near = |edge: f32| (1.0 - (self.time - edge).abs() / TWILIGHT).max(0.0);
+        near(DAWN).max(near(DUSK))
+    }
+
+    //Towards the sun, normalized. Below the horizon at night.
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let angle = (self.time - DAWN) / 24.0 * std::f32::consts::PI * 2.0;
+        //Tilted a little, so the sun doesn't light north and south faces exactly the same
+        Vector3::new(angle.cos(), angle.sin(), 0.3).normalize()
+    }
+
+    //The light that shades block faces: the sun by day and the moon, opposite it, by night.
+    //Straight up while they swap, so faces don't suddenly change shade at dawn and dusk.
+    pub fn light_direction(&self) -> Vector3<f32> {
+        let sun = self.sun_direction();
+        let light = if sun.y >= 0.0 { sun } else { -sun };
+        light.lerp(Vector3::unit_y(), self.twilight()).normalize()
+    }
+
+    pub fn light_color(&self) -> [f32; 3] {
+        let sun = lerp(SUN_COLOR, TWILIGHT_SUN_COLOR, self.twilight());
+        lerp(MOON_COLOR, sun, self.day())
+    }
+
+    //Scales sky light: 1 by day, NIGHT_DAYLIGHT at night
+    pub fn daylight(&self) -> f32 {
+        NIGHT_DAYLIGHT + (1.0 - NIGHT_DAYLIGHT) * self.day()
+    }
+
+    //Blends night, twilight and day_sky, the sky colour at noon. This is the sky at the horizon.
+    pub fn sky_color(&self, day_sky: [f32; 3]) -> [f32; 3] {
+        let sky = lerp(NIGHT_SKY, day_sky, self.day());
+        lerp(sky, TWILIGHT_SKY, self.twilight() * 0.6)
+    }
+
+    //The sky straight up. Twilight only colours the horizon.
+    pub fn zenith_color(&self, day_sky: [f32; 3]) -> [f32; 3] {
+        let sky = lerp(NIGHT_SKY, day_sky, self.day());
+        [sky[0] * ZENITH_SHADE, sky[1] * ZENITH_SHADE, sky[2] * ZENITH_SHADE]
+    }
+
+    //How much the stars show, 0 by day and 1 at night
+    pub fn stars(&self) -> f32 {
+        1.0 - self.day()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY_SKY: [f32; 3] = [0.1, 0.2, 0.3];
+
+    //Every quarter of an hour through the day
+    fn day() -> impl Iterator<Item = WorldClock> {
+        (0..96).map(|i| WorldClock::new(i as f32 / 4.0, 0.0))
+    }
+
+    fn assert_close(a: [f32; 3], b: [f32; 3]) {
+        assert!((0..3).all(|i| (a[i] - b[i]).abs() < 1e-6), "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn time_wraps_at_24_hours() {
+        assert_eq!(WorldClock::new(25.0, 0.0).time, 1.0);
+        assert_eq!(WorldClock::new(-1.0, 0.0).time, 23.0);
+        assert_eq!(WorldClock::new(24.0, 0.0).time, 0.0);
+        //An hour per second
+        let mut clock = WorldClock::new(23.5, 3600.0);
+        clock.update(1.0);
+        assert!((clock.time - 0.5).abs() < 1e-4, "{}", clock.time);
+        clock.speed = -3600.0;
+        clock.update(1.0);
+        assert!((clock.time - 23.5).abs() < 1e-4, "{}", clock.time);
+    }
+
+    #[test]
+    fn daylight_is_highest_at_noon_and_lowest_at_midnight() {
+        let noon = WorldClock::new(12.0, 0.0).daylight();
+        let midnight = WorldClock::new(0.0, 0.0).daylight();
+        assert_eq!(noon, 1.0);
+        assert_eq!(midnight, NIGHT_DAYLIGHT);
+        for clock in day() {
+            let daylight = clock.daylight();
+            assert!(daylight <= noon && daylight >= midnight, "{} at {}", daylight, clock.time);
+        }
+        //Rising through the morning
+        assert!(WorldClock::new(5.5, 0.0).daylight() < WorldClock::new(6.5, 0.0).daylight());
+        assert!(WorldClock::new(17.5, 0.0).daylight() > WorldClock::new(18.5, 0.0).daylight());
+    }
+
+    #[test]
+    fn sky_colour_stays_between_the_keys() {
+        assert_close(WorldClock::new(12.0, 0.0).sky_color(DAY_SKY), DAY_SKY);
+        assert_close(WorldClock::new(0.0, 0.0).sky_color(DAY_SKY), NIGHT_SKY);
+        let keys = [NIGHT_SKY, TWILIGHT_SKY, DAY_SKY];
+        for clock in day() {
+            let color = clock.sky_color(DAY_SKY);
+            for c in 0..3 {
+                let min = keys.iter().map(|k| k[c]).fold(f32::MAX, f32::min);
+                let max = keys.iter().map(|k| k[c]).fold(f32::MIN, f32::max);
+                assert!(color[c] >= min - 1e-6 && color[c] <= max + 1e-6, "{:?} at {}", color, clock.time);
+            }
+        }
+        //Dawn and dusk look alike, and are red like the twilight colour, unlike the blue day sky
+        let dawn = WorldClock::new(DAWN, 0.0).sky_color(DAY_SKY);
+        assert_close(dawn, WorldClock::new(DUSK, 0.0).sky_color(DAY_SKY));
+        assert!(dawn[0] > dawn[2], "{:?}", dawn);
+    }
+}
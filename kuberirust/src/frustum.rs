@@ -0,0 +1,77 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Vector4};
+
+use crate::player::Aabb;
+
+//The space the camera sees, as six planes facing inwards: left, right, bottom, top, near, far.
+//A point p is inside a plane when dot(plane, (p, 1)) >= 0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    //From projection * view (Gribb & Hartmann). Clip space z runs from 0 to w, as in wgpu.
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let row = |i: usize| view_proj.row(i);
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(2),
+            row(3) - row(2),
+        ];
+        Self { planes }
+    }
+
+    //False only if the box is certainly outside. Boxes near a corner of the frustum may be kept
+    //even though they are outside, which is fine for culling.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            //The corner furthest along the plane's normal
+            let x = if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x };
+            let y = if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y };
+            let z = if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z };
+            plane.dot(Vector4::new(x, y, z, 1.0)) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+    use cgmath::{Deg, Point3};
+
+    //At the origin looking along +x, with a square 90 degree view out to 100 blocks
+    fn frustum() -> Frustum {
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(0.0), Deg(0.0));
+        let projection = Projection::new(100, 100, Deg(90.0), 0.1, 100.0);
+        Frustum::from_matrix(projection.calc_matrix() * camera.calc_matrix())
+    }
+
+    fn cube(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb::new(Point3::new(x, y, z), Point3::new(x + 1.0, y + 1.0, z + 1.0))
+    }
+
+    #[test]
+    fn box_in_front_is_inside() {
+        assert!(frustum().intersects(&cube(10.0, -0.5, -0.5)));
+        assert!(frustum().intersects(&cube(98.0, 5.0, 5.0)));
+    }
+
+    #[test]
+    fn box_behind_is_outside() {
+        assert!(!frustum().intersects(&cube(-10.0, -0.5, -0.5)));
+    }
+
+    #[test]
+    fn box_past_the_far_or_a_side_plane_is_outside() {
+        assert!(!frustum().intersects(&cube(100.5, -0.5, -0.5)));
+        //From 10 to 11 blocks ahead the view reaches at most 11 blocks to either side
+        assert!(frustum().intersects(&cube(10.0, -0.5, 9.5)));
+        assert!(!frustum().intersects(&cube(10.0, -0.5, 11.5)));
+        assert!(!frustum().intersects(&cube(10.0, -12.5, -0.5)));
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::mesher::{Face, FACES};
+use crate::model::{BlockPos, BlockRegistry, BlockType, ChunkPos, World};
+
+const MAX_LIGHT: u8 = BlockRegistry::MAX_LIGHT;
+
+//Sky light comes from above and keeps its full level going straight down through air.
+//Block light comes from blocks with a light level in blocks.ron. Both lose a level per block
+//otherwise, and don't pass opaque blocks.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum LightKind {
+    Sky,
+    Block,
+}
+
+const KINDS: [LightKind; 2] = [LightKind::Sky, LightKind::Block];
+
+//Level a neighbour gets from a block lit with level, in the direction of face
+fn spread(kind: LightKind, level: u8, face: Face) -> u8 {
+    if kind == LightKind::Sky && face == Face::Bottom && level == MAX_LIGHT {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+fn step(pos: BlockPos, face: Face) -> BlockPos {
+    let n = face.normal();
+    pos.offset(n[0], n[1], n[2])
+}
+
+//Lighting works on loaded chunks only. There is no top of the world, so anything below an unloaded
+//chunk counts as open sky until that chunk comes in and shades it.
+impl World {
+    //None if the chunk holding pos isn't loaded
+    pub fn light(&self, pos: BlockPos, kind: LightKind) -> Option<u8> {
+        let chunk = self.chunks.get(&pos.chunk_pos(self.chunk_size))?;
+        let local = pos.local(self.chunk_size);
+        Some(match kind {
+            LightKind::Sky => chunk.sky_light(local),
+            LightKind::Block => chunk.block_light(local),
+        })
+    }
+
+    //Also marks the chunks whose meshes show the light as changed
+    fn set_light(&mut self, pos: BlockPos, kind: LightKind, level: u8, changed: &mut HashSet<ChunkPos>) {
+        let size = self.chunk_size;
+        if let Some(chunk) = self.chunks.get_mut(&pos.chunk_pos(size)) {
+            let local = pos.local(size);
+            match kind {
+                LightKind::Sky => chunk.set_sky_light(local, level),
+                LightKind::Block => chunk.set_block_light(local, level),
+            }
+            changed.extend(self.chunks_seeing(pos));
+        }
+    }
+
+    fn is_opaque_at(&self, pos: BlockPos) -> bool {
+        self.get_block(pos).map_or(false, |b| self.registry.is_opaque(b))
+    }
+
+    fn emission(&self, blocktype: BlockType) -> u8 {
+        self.registry.get(blocktype).light
+    }
+
+    //Light a chunk that was just inserted into chunks, and let light flow between it and its
+    //loaded neighbours. Returns every chunk whose mesh needs to be rebuilt, including pos.
+    pub fn light_new_chunk(&mut self, pos: ChunkPos) -> HashSet<ChunkPos> {
+        let mut changed = HashSet::new();
+        let size = self.chunk_size;
+        if !self.chunks.contains_key(&pos) {
+            return changed;
+        }
+        changed.insert(pos);
+        let origin = pos.origin(size);
+        let mut sky = VecDeque::new();
+        let mut block = VecDeque::new();
+        let mut unlit = VecDeque::new();
+
+        //Sunlight straight down each column, until something opaque
+        let above_loaded = self.chunks.contains_key(&pos.offset(0, 1, 0));
+        let below_loaded = self.chunks.contains_key(&pos.offset(0, -1, 0));
+        for x in 0..size {
+            for z in 0..size {
+                let top = origin.offset(x, size - 1, z);
+                let mut sunny = !above_loaded || self.light(top.offset(0, 1, 0), LightKind::Sky) == Some(MAX_LIGHT);
+                for y in (0..size).rev() {
+                    let p = origin.offset(x, y, z);
+                    sunny = sunny && !self.is_opaque_at(p);
+                    if !sunny {
+                        break;
+                    }
+                    self.set_light(p, LightKind::Sky, MAX_LIGHT, &mut changed);
+                    sky.push_back(p);
+                }
+                //The column below thought it was under open sky
+                let below = origin.offset(x, -1, z);
+                if below_loaded && !sunny && self.light(below, LightKind::Sky) == Some(MAX_LIGHT) {
+                    self.set_light(below, LightKind::Sky, 0, &mut changed);
+                    unlit.push_back((below, MAX_LIGHT));
+                }
+            }
+        }
+
+        let emitters: Vec<(BlockPos, u8)> = self.chunks[&pos]
+            .iter_blocks()
+            .map(|(local, blocktype)| (pos.to_world(local, size), self.emission(blocktype)))
+            .filter(|(_, light)| *light > 0)
+            .collect();
+        for (p, light) in emitters {
+            self.set_light(p, LightKind::Block, light, &mut changed);
+            block.push_back(p);
+        }
+
+        //Light on the neighbours' side of the borders flows in
+        for face in FACES.iter() {
+            let n = face.normal();
+            if !self.chunks.contains_key(&pos.offset(n[0], n[1], n[2])) {
+                continue;
+            }
+            for i in 0..size {
+                for j in 0..size {
+                    let mut local = [0; 3];
+                    let a = n.iter().position(|c| *c != 0).unwrap();
+                    local[a] = if n[a] > 0 { size } else { -1 };
+                    local[(a + 1) % 3] = i;
+                    local[(a + 2) % 3] = j;
+                    let p = origin.offset(local[0], local[1], local[2]);
+                    if self.light(p, LightKind::Sky).unwrap_or(0) > 0 {
+                        sky.push_back(p);
+                    }
+                    if self.light(p, LightKind::Block).unwrap_or(0) > 0 {
+                        block.push_back(p);
+                    }
+                }
+            }
+        }
+
+        self.unflood(LightKind::Sky, unlit, &mut sky, &mut changed);
+        self.flood(LightKind::Sky, sky, &mut changed);
+        self.flood(LightKind::Block, block, &mut changed);
+        changed
+    }
+
+    //Update the light around pos after its block was replaced by set_block.
+    //Returns the chunks whose meshes need to be rebuilt.
+    pub fn relight_block(&mut self, pos: BlockPos, old: BlockType) -> HashSet<ChunkPos> {
+        let mut changed = HashSet::new();
+        let new = match self.get_block(pos) {
+            Some(blocktype) => blocktype,
+            None => return changed,
+        };
+        let opaque = self.registry.is_opaque(new);
+        for kind in KINDS.iter().cloned() {
+            let level = self.light(pos, kind).unwrap_or(0);
+            let emitted = if kind == LightKind::Block { self.emission(new) } else { 0 };
+            let mut unlit = VecDeque::new();
+            let mut lit = VecDeque::new();
+
+            //Whatever lit this block may have lit its surroundings through it
+            if level > 0 && (opaque || (kind == LightKind::Block && self.emission(old) > 0)) {
+                self.set_light(pos, kind, 0, &mut changed);
+                unlit.push_back((pos, level));
+            }
+            if !opaque {
+                //Light flows in from around it
+                for face in FACES.iter() {
+                    let n = step(pos, *face);
+                    match self.light(n, kind) {
+                        Some(l) if l > 0 => lit.push_back(n),
+                        None if kind == LightKind::Sky && *face == Face::Top => {
+                            self.set_light(pos, kind, MAX_LIGHT, &mut changed);
+                            lit.push_back(pos);
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            if emitted > 0 {
+                self.set_light(pos, kind, emitted, &mut changed);
+                lit.push_back(pos);
+            }
+            self.unflood(kind, unlit, &mut lit, &mut changed);
+            self.flood(kind, lit, &mut changed);
+        }
+        changed
+    }
+
+    //Breadth first from blocks whose light went up, raising neighbours that are darker than they
+    //should be
+    fn flood(&mut self, kind: LightKind, mut queue: VecDeque<BlockPos>, changed: &mut HashSet<ChunkPos>) {
+        while let Some(pos) = queue.pop_front() {
+            let level = match self.light(pos, kind) {
+                Some(level) if level > 1 => level,
+                _ => continue,
+            };
+            for face in FACES.iter() {
+                let n = step(pos, *face);
+                let current = match self.light(n, kind) {
+                    Some(current) => current,
+                    None => continue,
+                };
+                let new = spread(kind, level, *face);
+                if current < new && !self.is_opaque_at(n) {
+                    self.set_light(n, kind, new, changed);
+                    queue.push_back(n);
+                }
+            }
+        }
+    }
+
+    //Breadth first from blocks that went dark, given with the level they had. Darkens everything
+    //that got its light from them, and adds the blocks lit from elsewhere to lit so flood can fill
+    //the hole again.
+    fn unflood(
+        &mut self,
+        kind: LightKind,
+        mut queue: VecDeque<(BlockPos, u8)>,
+        lit: &mut VecDeque<BlockPos>,
+        changed: &mut HashSet<ChunkPos>,
+    ) {
+        while let Some((pos, level)) = queue.pop_front() {
+            for face in FACES.iter() {
+                let n = step(pos, *face);
+                let current = match self.light(n, kind) {
+                    Some(current) if current > 0 => current,
+                    _ => continue,
+                };
+                if current < level || (current == MAX_LIGHT && spread(kind, level, *face) == MAX_LIGHT) {
+                    //Light blocks keep their own light
+                    let own = match (kind, self.get_block(n)) {
+                        (LightKind::Block, Some(blocktype)) => self.emission(blocktype),
+                        _ => 0,
+                    };
+                    self.set_light(n, kind, own, changed);
+                    queue.push_back((n, current));
+                    if own > 0 {
+                        lit.push_back(n);
+                    }
+                } else {
+                    lit.push_back(n);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Chunk;
+    use std::sync::Arc;
+
+    const SIZE: i32 = 16;
+
+    //The chunks from -SIZE to SIZE on every axis, top ones first
+    fn chunk_positions() -> Vec<ChunkPos> {
+        let mut positions = Vec::new();
+        for y in (-1..=0).rev() {
+            for x in -1..=0 {
+                for z in -1..=0 {
+                    positions.push(ChunkPos::new(x, y, z));
+                }
+            }
+        }
+        positions
+    }
+
+    fn empty_world() -> World {
+        World::new(SIZE, Arc::new(BlockRegistry::builtin().unwrap()))
+    }
+
+    //Insert a chunk and light it the way Model does when a chunk is loaded
+    fn load(world: &mut World, pos: ChunkPos, chunk: Chunk) -> HashSet<ChunkPos> {
+        world.chunks.insert(pos, chunk);
+        world.light_new_chunk(pos)
+    }
+
+    //Chunks filled with blocks(pos), loaded and lit one by one
+    fn world_of<F: Fn(BlockPos) -> BlockType>(blocks: F) -> World {
+        let mut world = empty_world();
+        for pos in chunk_positions() {
+            let mut chunk = Chunk::new(SIZE);
+            for (local, _) in Chunk::new(SIZE).iter() {
+                chunk.set(local, blocks(pos.to_world(local, SIZE)));
+            }
+            load(&mut world, pos, chunk);
+        }
+        world
+    }
+
+    //Empty chunks, all under open sky
+    fn world() -> World {
+        world_of(|_| BlockType::AIR)
+    }
+
+    fn block(world: &World, name: &str) -> BlockType {
+        world.registry.id(name).unwrap()
+    }
+
+    #[test]
+    fn opaque_block_shades_the_sky_light_below_it() {
+        let mut world = world();
+        let stone = block(&world, "stone");
+        let top = BlockPos::new(3, 5, 3);
+        assert_eq!(world.light(top.offset(0, -1, 0), LightKind::Sky), Some(MAX_LIGHT));
+
+        world.set_block(top, stone);
+        assert_eq!(world.light(top, LightKind::Sky), Some(0));
+        //Lit from the side now, all the way down into the chunk below
+        assert_eq!(world.light(top.offset(0, -1, 0), LightKind::Sky), Some(MAX_LIGHT - 1));
+        assert_eq!(world.light(top.offset(0, -20, 0), LightKind::Sky), Some(MAX_LIGHT - 1));
+
+        world.set_block(top, BlockType::AIR);
+        assert_eq!(world.light(top, LightKind::Sky), Some(MAX_LIGHT));
+        assert_eq!(world.light(top.offset(0, -1, 0), LightKind::Sky), Some(MAX_LIGHT));
+        assert_eq!(world.light(top.offset(0, -20, 0), LightKind::Sky), Some(MAX_LIGHT));
+    }
+
+    #[test]
+    fn lamp_light_falls_off_by_one_per_block() {
+        let mut world = world();
+        let lamp = block(&world, "lamp");
+        let level = world.registry.get(lamp).light;
+        let pos = BlockPos::new(-2, 3, 4);
+        world.set_block(pos, lamp);
+        assert_eq!(world.light(pos, LightKind::Block), Some(level));
+        assert_eq!(world.light(pos.offset(4, 0, 0), LightKind::Block), Some(level - 4));
+        assert_eq!(world.light(pos.offset(-2, 2, 1), LightKind::Block), Some(level - 5));
+        assert_eq!(world.light(pos.offset(0, 0, -(level as i32)), LightKind::Block), Some(0));
+        //Sky light is separate
+        assert_eq!(world.light(pos.offset(1, 0, 0), LightKind::Sky), Some(MAX_LIGHT));
+
+        world.set_block(pos, BlockType::AIR);
+        for offset in &[[0, 0, 0], [4, 0, 0], [-2, 2, 1]] {
+            let p = pos.offset(offset[0], offset[1], offset[2]);
+            assert_eq!(world.light(p, LightKind::Block), Some(0), "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn light_crosses_into_a_neighbour_loaded_later() {
+        let mut world = empty_world();
+        let lamp = block(&world, "lamp");
+        let level = world.registry.get(lamp).light;
+        load(&mut world, ChunkPos::new(0, 0, 0), Chunk::new(SIZE));
+        world.set_block(BlockPos::new(1, 5, 1), lamp);
+        assert_eq!(world.light(BlockPos::new(-1, 5, 1), LightKind::Block), None);
+
+        let changed = load(&mut world, ChunkPos::new(-1, 0, 0), Chunk::new(SIZE));
+        assert!(changed.contains(&ChunkPos::new(-1, 0, 0)));
+        assert_eq!(world.light(BlockPos::new(-1, 5, 1), LightKind::Block), Some(level - 2));
+        assert_eq!(world.light(BlockPos::new(-5, 5, 1), LightKind::Block), Some(level - 6));
+
+        //And back the other way, from a lamp placed in the new chunk
+        world.set_block(BlockPos::new(-1, 10, 10), lamp);
+        assert_eq!(world.light(BlockPos::new(3, 10, 10), LightKind::Block), Some(level - 4));
+    }
+
+    #[test]
+    fn edits_match_lighting_from_scratch() {
+        let registry = BlockRegistry::builtin().unwrap();
+        let stone = registry.id("stone").unwrap();
+        let lamp = registry.id("lamp").unwrap();
+        //A roof with a hole in it, so there is shade to work with
+        let mut world = world_of(|p| {
+            if p.y == 8 && (p.x.abs() > 2 || p.z.abs() > 2) {
+                stone
+            } else {
+                BlockType::AIR
+            }
+        });
+        let mut seed: u32 = 12345;
+        let mut random = |n: i32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ((seed >> 16) % n as u32) as i32
+        };
+        for _ in 0..300 {
+            let pos = BlockPos::new(random(2 * SIZE) - SIZE, random(2 * SIZE) - SIZE, random(2 * SIZE) - SIZE);
+            let blocktype = match random(3) {
+                0 => stone,
+                1 => lamp,
+                _ => BlockType::AIR,
+            };
+            world.set_block(pos, blocktype);
+        }
+
+        //The same blocks, lit as the chunks come in
+        let fresh = world_of(|p| world.get_block(p).unwrap());
+        for (pos, chunk) in world.chunks.iter() {
+            for (local, _) in chunk.iter() {
+                let p = pos.to_world(local, SIZE);
+                for kind in KINDS.iter() {
+                    assert_eq!(world.light(p, *kind), fresh.light(p, *kind), "{:?} light at {:?}", kind, p);
+                }
+            }
+        }
+    }
+}
@@ -5,10 +5,29 @@ use wgpu::util::DeviceExt;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
-    window::{Window, WindowBuilder},
+    window::Window,
 };
 
+use anyhow::{bail, Context};
+use std::path::{Path, PathBuf};
+
 mod model;
+mod chunk;
+mod worldgen;
+mod mesher;
+mod lighting;
+mod clock;
+mod sky;
+mod shadow;
+mod frustum;
+mod raycast;
+mod player;
+mod outline;
+mod registry;
+mod atlas;
+mod storage;
+mod streaming;
+mod workers;
 mod texture;
 mod camera;
 
@@ -36,14 +55,120 @@ impl Uniforms {
     }
 }
 
+//One directional light, the sun or the moon. Laid out to match the Light block in shader.frag.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Light {
+    //Towards the light, normalized
+    direction: [f32; 3],
+    //Scales sky light, lower at night
+    daylight: f32,
+    color: [f32; 3],
+    //Fraction of the light that reaches faces turned away from it
+    ambient: f32,
+    //Projection * view from the light, for the shadow map
+    view_proj: [[f32; 4]; 4],
+}
+
+impl Light {
+    fn new<V: Into<cgmath::Vector3<f32>>>(direction: V, color: [f32; 3], ambient: f32) -> Self {
+        Self {
+            direction: direction.into().normalize().into(),
+            daylight: 1.0,
+            color,
+            ambient,
+            view_proj: cgmath::Matrix4::identity().into(),
+        }
+    }
+
+    //The sun or moon at the clock's time of day, casting shadows around center
+    fn from_clock(clock: &clock::WorldClock, ambient: f32, center: cgmath::Point3<f32>) -> Self {
+        let direction = clock.light_direction();
+        Self {
+            daylight: clock.daylight(),
+            view_proj: shadow::light_view_proj(direction, center).into(),
+            ..Self::new(direction, clock.light_color(), ambient)
+        }
+    }
+}
+
+//Hides far away geometry in the sky colour. Laid out to match the Fog block in shader.frag.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Fog {
+    color: [f32; 3],
+    //Exponential fog, per block
+    density: f32,
+    //Linear fog, in blocks from the camera
+    start: f32,
+    end: f32,
+    //FogMode
+    mode: u32,
+    _padding: u32,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum FogMode {
+    Off,
+    Linear,
+    Exponential,
+}
+
+impl FogMode {
+    //As written after --fog
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(FogMode::Off),
+            "linear" => Some(FogMode::Linear),
+            "exp" => Some(FogMode::Exponential),
+            _ => None,
+        }
+    }
+}
+
+impl Fog {
+    //Thick enough to hide everything beyond distance
+    fn new(mode: FogMode, color: [f32; 3], distance: f32) -> Self {
+        Self {
+            color,
+            density: 3.0 / distance,
+            start: distance * 0.5,
+            end: distance,
+            mode: mode as u32,
+            _padding: 0,
+        }
+    }
+}
+
+const SCREENSHOT_WIDTH: u32 = 800;
+const SCREENSHOT_HEIGHT: u32 = 600;
+const DEFAULT_WORLD_DIR: &str = "world";
+//Sky at the horizon and fog colour at noon, linear RGB. Change with --sky r,g,b.
+const DEFAULT_SKY_COLOR: [f32; 3] = [0.1, 0.2, 0.3];
+//How far away blocks can be picked, in blocks. Change with --reach.
+const DEFAULT_REACH: f32 = 6.0;
+//Fraction of the sun or moon light that reaches faces turned away from it
+const AMBIENT_LIGHT: f32 = 0.4;
+//Button ids as numbered by winit on X11 and Windows
+const LEFT_MOUSE_BUTTON: u32 = 1;
+const RIGHT_MOUSE_BUTTON: u32 = 3;
+
 struct State {
-    surface: wgpu::Surface,
+    //None when rendering headless
+    surface: Option<wgpu::Surface>,
     device: wgpu::Device,
     queue: wgpu::Queue,
+    //Size and format of what we render to, also when there is no swap chain
     sc_desc: wgpu::SwapChainDescriptor,
-    swap_chain: wgpu::SwapChain,
+    swap_chain: Option<wgpu::SwapChain>,
     size: winit::dpi::PhysicalSize<u32>,
     render_pipeline: wgpu::RenderPipeline,
+    //Drawn first, in a pass of its own
+    sky_pipeline: wgpu::RenderPipeline,
+    sky: sky::Sky,
+    //Lines around the targeted block
+    outline_pipeline: wgpu::RenderPipeline,
+    outline: outline::BlockOutline,
     obj_model: model::Model,
     #[allow(dead_code)]
     camera: camera::Camera,                     
@@ -52,10 +177,39 @@ struct State {
     uniforms: Uniforms,
     uniform_buffer: wgpu::Buffer,
     uniform_bind_group: wgpu::BindGroup,
+    light: Light,
+    light_buffer: wgpu::Buffer,
+    light_bind_group: wgpu::BindGroup,
+    shadow_map: shadow::ShadowMap,
+    fog: Fog,
+    fog_buffer: wgpu::Buffer,
+    //Time of day, moves the sun and changes the sky
+    clock: clock::WorldClock,
+    //Sky colour at noon, the clock darkens and tints it
+    day_sky: [f32; 3],
 
     #[allow(dead_code)]
     mouse_pressed: bool,
     depth_texture: texture::Texture,
+    //Block the camera looks at, if it is within reach
+    target: Option<raycast::RaycastHit>,
+    //In blocks
+    reach: f32,
+    //Mouse looks around and clicking breaks and places blocks, instead of dragging to look
+    interact: bool,
+    //Block placed with the right mouse button
+    selected: model::BlockType,
+    //Walks with gravity and collisions. The camera follows its eye.
+    player: player::Player,
+    //Fly freely through blocks instead of walking
+    noclip: bool,
+}
+
+//What differs between the terrain, outline and sky pipelines besides their shaders
+struct PipelineOptions {
+    //None for pipelines used in passes without a depth attachment
+    depth_format: Option<wgpu::TextureFormat>,
+    topology: wgpu::PrimitiveTopology,
 }
 
 fn create_render_pipeline(
@@ -65,13 +219,14 @@ fn create_render_pipeline(
     vertex_descs: &[wgpu::VertexBufferDescriptor],
     vs_src: wgpu::ShaderModuleSource,
     fs_src: wgpu::ShaderModuleSource,
+    options: PipelineOptions,
 ) -> wgpu::RenderPipeline {
     let vs_module = device.create_shader_module(vs_src);
     let fs_module = device.create_shader_module(fs_src);
 
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
-        layout: Some(&layout),
+        layout: Some(layout),
         vertex_stage: wgpu::ProgrammableStageDescriptor {
             module: &vs_module,
             entry_point: "main",
@@ -88,32 +243,45 @@ fn create_render_pipeline(
             depth_bias_clamp: 0.0,
             clamp_depth: false,
         }),
-        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+        primitive_topology: options.topology,
         color_states: &[wgpu::ColorStateDescriptor {
             format: color_format,
             color_blend: wgpu::BlendDescriptor::REPLACE,
             alpha_blend: wgpu::BlendDescriptor::REPLACE,
             write_mask: wgpu::ColorWrite::ALL,
         }],
-        depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
-            format: texture::Texture::DEPTH_FORMAT,
+        depth_stencil_state: options.depth_format.map(|format| wgpu::DepthStencilStateDescriptor {
+            format,
             depth_write_enabled: true,
             depth_compare: wgpu::CompareFunction::Less,
             stencil: wgpu::StencilStateDescriptor::default(),
-        }),        
+        }),
         sample_count: 1,
         sample_mask: !0,
         alpha_to_coverage_enabled: false,
         vertex_state: wgpu::VertexStateDescriptor {
-            //index_format: wgpu::IndexFormat::Uint32,
-            index_format: wgpu::IndexFormat::Uint16,
+            index_format: wgpu::IndexFormat::Uint32,
             vertex_buffers: vertex_descs,
         },
     })
 }
 
+async fn request_device(adapter: &wgpu::Adapter) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let device_and_queue = adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::default(),
+                shader_validation: true,
+            },
+            None, // Trace path
+        )
+        .await?;
+    Ok(device_and_queue)
+}
+
 impl State {
-    async fn new(window: &Window) -> Self {
+    async fn new(window: &Window, world_dir: Option<&Path>) -> Self {
         let size = window.inner_size();
 
         // The instance is a handle to our GPU
@@ -127,17 +295,7 @@ impl State {
             })
             .await
             .unwrap();
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    features: wgpu::Features::empty(),
-                    limits: wgpu::Limits::default(),
-                    shader_validation: true,
-                },
-                None, // Trace path
-            )
-            .await
-            .unwrap();
+        let (device, queue) = request_device(&adapter).await.unwrap();
 
         let sc_desc = wgpu::SwapChainDescriptor {
             usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
@@ -147,7 +305,49 @@ impl State {
             present_mode: wgpu::PresentMode::Fifo,
         };
         let swap_chain = device.create_swap_chain(&surface, &sc_desc);
-       
+
+        Self::with_device(Some(surface), device, queue, sc_desc, Some(swap_chain), world_dir)
+    }
+
+    // Without window and surface. Any adapter will do, also software ones like lavapipe.
+    async fn new_headless(width: u32, height: u32, world_dir: Option<&Path>) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::Default,
+                compatible_surface: None,
+            })
+            .await
+            .context("No graphics adapter found")?;
+        let (device, queue) = request_device(&adapter).await?;
+
+        // RGBA, so the pixels can go straight into an image::RgbaImage
+        let sc_desc = wgpu::SwapChainDescriptor {
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width,
+            height,
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+
+        let mut state = Self::with_device(None, device, queue, sc_desc, None, world_dir);
+        //There is only one frame, so everything in view must be there
+        state
+            .obj_model
+            .load_around(&state.device, &state.queue, state.camera.position)?;
+        Ok(state)
+    }
+
+    fn with_device(
+        surface: Option<wgpu::Surface>,
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        sc_desc: wgpu::SwapChainDescriptor,
+        swap_chain: Option<wgpu::SwapChain>,
+        world_dir: Option<&Path>,
+    ) -> Self {
+        let size = winit::dpi::PhysicalSize::new(sc_desc.width, sc_desc.height);
+
         let texture_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[
@@ -171,10 +371,33 @@ impl State {
                 label: Some("texture_bind_group_layout"),
             });
 
-        let camera = camera::Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+        let now = std::time::Instant::now(); 
+        let mut obj_model = match world_dir {
+            Some(dir) => model::Model::open(dir),
+            None => model::Model::new(),
+        }
+        .unwrap();
+        
+        obj_model.load(
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+        ).unwrap();
+
+        //Continue where the world was left, if it was saved with a camera
+        let camera = match obj_model.storage.as_ref().and_then(|s| s.meta.camera) {
+            Some(saved) => camera::Camera::new(saved.position, cgmath::Rad(saved.yaw), cgmath::Rad(saved.pitch)),
+            None => camera::Camera::new((0.0, 22.0, 30.0), cgmath::Deg(-90.0), cgmath::Deg(-30.0)),
+        };
         let projection =
             camera::Projection::new(sc_desc.width, sc_desc.height, cgmath::Deg(45.0), 0.1, 100.0);
         let camera_controller = camera::CameraController::new(4.0, 0.4);
+        let player = player::Player::at_eye(camera.position);
+        let clock = obj_model
+            .storage
+            .as_ref()
+            .and_then(|s| s.meta.clock)
+            .unwrap_or_default();
 
         let mut uniforms = Uniforms::new();
         uniforms.update_view_proj(&camera, &projection);
@@ -189,7 +412,7 @@ impl State {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStage::VERTEX,
+                    visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
                     ty: wgpu::BindingType::UniformBuffer {
                         dynamic: false,
                         min_binding_size: None,
@@ -208,25 +431,100 @@ impl State {
             label: Some("uniform_bind_group"),
         });
 
-        let now = std::time::Instant::now(); 
-        let mut obj_model = model::Model::new().unwrap();
-        
-        obj_model.load(
-            &device,
-            &queue,
-            &texture_bind_group_layout,
-        );
+        let light = Light::from_clock(&clock, AMBIENT_LIGHT, camera.position);
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Light Buffer"),
+            contents: bytemuck::cast_slice(&[light]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        //Fog ends where chunks may still be loading, so they don't pop in
+        let fog_distance = ((obj_model.chunk_manager.view_radius - 1) * obj_model.world.chunk_size) as f32;
+        let fog = Fog::new(FogMode::Linear, clock.sky_color(DEFAULT_SKY_COLOR), fog_distance);
+        let fog_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::cast_slice(&[fog]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let shadow_map = shadow::ShadowMap::new(&device, &light_buffer);
+
+        //Light at binding 0, fog at binding 1, then the shadow map and its comparison sampler
+        let light_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer {
+                            dynamic: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            component_type: wgpu::TextureComponentType::Float,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: true },
+                        count: None,
+                    },
+                ],
+                label: Some("light_bind_group_layout"),
+            });
+
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &light_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer(light_buffer.slice(..)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(fog_buffer.slice(..)),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&shadow_map.texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&shadow_map.texture.sampler),
+                },
+            ],
+            label: Some("light_bind_group"),
+        });
 
         let depth_texture =
         texture::Texture::create_depth_texture(&device, &sc_desc, "depth_texture");
 
-        println!("Elapsed (Original): {:?}", std::time::Instant::now());        
+        println!("Elapsed (Original): {:?}", now.elapsed());        
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
                 bind_group_layouts: &[
                     &texture_bind_group_layout,
                     &uniform_bind_group_layout,
+                    &light_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
@@ -239,9 +537,63 @@ impl State {
             &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
             wgpu::include_spirv!("shader.vert.spv"),
             wgpu::include_spirv!("shader.frag.spv"),
+            PipelineOptions {
+                depth_format: Some(texture::Texture::DEPTH_FORMAT),
+                topology: wgpu::PrimitiveTopology::TriangleList,
+            },
+        );
+
+        let outline_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Outline Pipeline Layout"),
+                bind_group_layouts: &[&uniform_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let outline_pipeline = create_render_pipeline(
+            &device,
+            &outline_pipeline_layout,
+            sc_desc.format,
+            &[outline::BlockOutline::desc()],
+            wgpu::include_spirv!("outline.vert.spv"),
+            wgpu::include_spirv!("outline.frag.spv"),
+            PipelineOptions {
+                depth_format: Some(texture::Texture::DEPTH_FORMAT),
+                topology: wgpu::PrimitiveTopology::LineList,
+            },
+        );
+        let outline = outline::BlockOutline::new(&device);
+
+        let sky = sky::Sky::new(&device, &clock, DEFAULT_SKY_COLOR);
+        let sky_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Sky Pipeline Layout"),
+                bind_group_layouts: &[&uniform_bind_group_layout, &sky.bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        //No depth, the terrain pass clears the depth buffer and draws over all of the sky
+        let sky_pipeline = create_render_pipeline(
+            &device,
+            &sky_pipeline_layout,
+            sc_desc.format,
+            &[],
+            wgpu::include_spirv!("sky.vert.spv"),
+            wgpu::include_spirv!("sky.frag.spv"),
+            PipelineOptions {
+                depth_format: None,
+                topology: wgpu::PrimitiveTopology::TriangleList,
+            },
         );
    
 
+        //First block in blocks.ron
+        let selected = obj_model
+            .world
+            .registry
+            .iter()
+            .next()
+            .map(|(blocktype, _)| blocktype)
+            .unwrap_or(model::BlockType::AIR);
+
         Self {
             surface,
             device,
@@ -249,6 +601,10 @@ impl State {
             sc_desc,
             swap_chain,
             render_pipeline,
+            sky_pipeline,
+            sky,
+            outline_pipeline,
+            outline,
             obj_model,
             camera,
             projection,
@@ -256,9 +612,23 @@ impl State {
             uniform_buffer,
             uniform_bind_group,
             uniforms,
+            light,
+            light_buffer,
+            light_bind_group,
+            shadow_map,
+            fog,
+            fog_buffer,
+            clock,
+            day_sky: DEFAULT_SKY_COLOR,
             size,
             mouse_pressed: false,
             depth_texture,
+            target: None,
+            reach: DEFAULT_REACH,
+            interact: false,
+            selected,
+            player,
+            noclip: false,
         }
     }
 
@@ -267,13 +637,38 @@ impl State {
         self.size = new_size;
         self.sc_desc.width = new_size.width;
         self.sc_desc.height = new_size.height;
-        self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+        if let Some(surface) = &self.surface {
+            self.swap_chain = Some(self.device.create_swap_chain(surface, &self.sc_desc));
+        }
         self.depth_texture =
         texture::Texture::create_depth_texture(&self.device, &self.sc_desc, "depth_texture");
     }
 
     fn input(&mut self, event: &DeviceEvent) -> bool {
         match event {
+            DeviceEvent::Key(KeyboardInput {
+                virtual_keycode: Some(VirtualKeyCode::Tab),
+                state: ElementState::Pressed,
+                ..
+            }) => {
+                self.interact = !self.interact;
+                self.mouse_pressed = false;
+                true
+            }
+            DeviceEvent::Key(KeyboardInput {
+                virtual_keycode: Some(VirtualKeyCode::F),
+                state: ElementState::Pressed,
+                ..
+            }) => {
+                self.noclip = !self.noclip;
+                self.player = player::Player::at_eye(self.camera.position);
+                true
+            }
+            DeviceEvent::Key(KeyboardInput {
+                virtual_keycode: Some(key),
+                state: ElementState::Pressed,
+                ..
+            }) if self.select_block(*key) => true,
             DeviceEvent::Key(
                 KeyboardInput {
                     virtual_keycode: Some(key),
@@ -286,14 +681,25 @@ impl State {
                 true
             }
             DeviceEvent::Button {
-                button: 1, // Left Mouse Button
+                button: LEFT_MOUSE_BUTTON,
                 state,
             } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
+                if !self.interact {
+                    self.mouse_pressed = *state == ElementState::Pressed;
+                } else if *state == ElementState::Pressed {
+                    self.break_block();
+                }
+                true
+            }
+            DeviceEvent::Button {
+                button: RIGHT_MOUSE_BUTTON,
+                state: ElementState::Pressed,
+            } if self.interact => {
+                self.place_block();
                 true
             }
             DeviceEvent::MouseMotion { delta } => {
-                if self.mouse_pressed {
+                if self.mouse_pressed || self.interact {
                     self.camera_controller.process_mouse(delta.0, delta.1);
                 }
                 true
@@ -302,8 +708,92 @@ impl State {
         }
     }
 
+    //Number keys pick the block to place, in blocks.ron order
+    fn select_block(&mut self, key: VirtualKeyCode) -> bool {
+        use VirtualKeyCode::*;
+        let keys = [Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9];
+        let index = match keys.iter().position(|k| *k == key) {
+            Some(index) => index,
+            None => return false,
+        };
+        if let Some((blocktype, _)) = self.obj_model.world.registry.iter().nth(index) {
+            self.selected = blocktype;
+        }
+        true
+    }
+
+    fn break_block(&mut self) {
+        if let Some(hit) = self.target.take() {
+            self.obj_model.world.remove_block(hit.block);
+        }
+    }
+
+    //Against the targeted face, unless the player is in the way
+    fn place_block(&mut self) {
+        let hit = match self.target {
+            Some(hit) if hit.normal != [0, 0, 0] => hit,
+            _ => return,
+        };
+        let pos = hit.adjacent();
+        if player::Aabb::player(self.camera.position).intersects(&player::Aabb::block(pos)) {
+            return;
+        }
+        let world = &mut self.obj_model.world;
+        if world.get_block(pos).map_or(false, |b| !world.registry.is_solid(b)) {
+            world.set_block(pos, self.selected);
+            self.target = None;
+        }
+    }
+
+    //color is the sky at the horizon at noon, and what the fog fades into
+    fn set_sky_color(&mut self, color: [f32; 3]) {
+        self.day_sky = color;
+        self.update_sky();
+    }
+
+    //Move the sun and its shadows and recolour the sky for the clock's time of day
+    fn update_sky(&mut self) {
+        self.light = Light::from_clock(&self.clock, self.light.ambient, self.camera.position);
+        self.fog.color = self.clock.sky_color(self.day_sky);
+        self.queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[self.light]));
+        self.queue.write_buffer(&self.fog_buffer, 0, bytemuck::cast_slice(&[self.fog]));
+        self.sky.update(&self.queue, &self.clock, self.day_sky);
+    }
+
+    fn set_fog_mode(&mut self, mode: FogMode) {
+        self.fog.mode = mode as u32;
+        self.queue.write_buffer(&self.fog_buffer, 0, bytemuck::cast_slice(&[self.fog]));
+    }
+
     fn update(&mut self, dt: std::time::Duration) {
-        self.camera_controller.update_camera(&mut self.camera, dt);
+        self.clock.update(dt.as_secs_f32());
+        if self.noclip {
+            self.camera_controller.update_camera(&mut self.camera, dt);
+        } else {
+            self.camera_controller.rotate_camera(&mut self.camera, dt.as_secs_f32());
+            let movement = self.camera_controller.walk_velocity(&self.camera);
+            let jump = self.camera_controller.jumping();
+            self.player
+                .update(&self.obj_model.world, movement, jump, dt.as_secs_f32());
+            self.camera.position = self.player.eye();
+        }
+        if let Err(e) = self
+            .obj_model
+            .update_chunks(&self.device, &self.queue, self.camera.position)
+        {
+            eprintln!("Unable to load chunks: {:?}", e);
+        }
+        self.target = self
+            .obj_model
+            .world
+            .raycast(self.camera.position, self.camera.direction(), self.reach);
+        self.outline.update(&self.queue, self.target.map(|hit| hit.block));
+        let frustum = frustum::Frustum::from_matrix(self.projection.calc_matrix() * self.camera.calc_matrix());
+        self.obj_model.cull(&frustum);
+        //After the camera moved, the shadow map follows it
+        self.update_sky();
+        self.obj_model
+            .cull_shadows(&frustum::Frustum::from_matrix(self.light.view_proj.into()));
         self.uniforms
             .update_view_proj(&self.camera, &self.projection);
         self.queue.write_buffer(
@@ -314,7 +804,10 @@ impl State {
     }
 
     fn render(&mut self) -> Result<(), wgpu::SwapChainError> {
-        let frame = self.swap_chain.get_current_frame()?.output;
+        let frame = match &mut self.swap_chain {
+            Some(swap_chain) => swap_chain.get_current_frame()?.output,
+            None => return Ok(()),
+        };
 
         let mut encoder = self
             .device
@@ -322,21 +815,62 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        self.draw(&mut encoder, &frame.view);
+
+        self.queue.submit(iter::once(encoder.finish()));
+
+        Ok(())
+    }
+
+    // Record the passes that draw the scene into view
+    fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
         {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: &self.shadow_map.texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            shadow_pass.set_pipeline(&self.shadow_map.pipeline);
+            shadow_pass.draw_shadow_casters(&self.obj_model, &self.shadow_map.bind_group);
+        }
+        {
+            let mut sky_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
-                    attachment: &frame.view,
+                    attachment: view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
+                            r: self.fog.color[0] as f64,
+                            g: self.fog.color[1] as f64,
+                            b: self.fog.color[2] as f64,
                             a: 1.0,
                         }),
                         store: true,
                     },
                 }],
+                depth_stencil_attachment: None,
+            });
+            sky_pass.set_pipeline(&self.sky_pipeline);
+            sky_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            sky_pass.set_bind_group(1, &self.sky.bind_group, &[]);
+            sky_pass.draw(0..3, 0..1);
+        }
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: true,
+                    },
+                }],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
                     attachment: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
@@ -352,18 +886,262 @@ impl State {
                 &self.obj_model,
                 //0..self.instances.len() as u32,
                 &self.uniform_bind_group,
-                //&self.light_bind_group,
+                &self.light_bind_group,
             );
+
+            if self.outline.block.is_some() {
+                render_pass.set_pipeline(&self.outline_pipeline);
+                render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, self.outline.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(self.outline.index_buffer.slice(..));
+                render_pass.draw_indexed(0..self.outline.num_indexes, 0, 0..1);
+            }
+        }
+    }
+
+    //Save the world with the camera and the time of day as they are now
+    fn save(&mut self) -> anyhow::Result<()> {
+        if let Some(storage) = &mut self.obj_model.storage {
+            storage.meta.clock = Some(self.clock);
+            storage.meta.camera = Some(storage::SavedCamera {
+                position: self.camera.position.into(),
+                yaw: self.camera.yaw().0,
+                pitch: self.camera.pitch().0,
+            });
         }
+        self.obj_model.save()
+    }
 
+    // Render into an offscreen texture and read it back. Works with and without a window.
+    fn screenshot(&mut self) -> anyhow::Result<image::RgbaImage> {
+        let (width, height) = (self.sc_desc.width, self.sc_desc.height);
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth: 1,
+        };
+        //Same format as the pipeline renders to
+        let format = self.sc_desc.format;
+        let bgra = match format {
+            wgpu::TextureFormat::Rgba8UnormSrgb | wgpu::TextureFormat::Rgba8Unorm => false,
+            wgpu::TextureFormat::Bgra8UnormSrgb | wgpu::TextureFormat::Bgra8Unorm => true,
+            _ => bail!("Can not take screenshots of format {:?}", format),
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Screenshot Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT | wgpu::TextureUsage::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Rows in the buffer must be aligned
+        let unpadded_bytes_per_row = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = (unpadded_bytes_per_row + align - 1) / align * align;
+        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Screenshot Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::COPY_DST | wgpu::BufferUsage::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Screenshot Encoder"),
+            });
+        self.draw(&mut encoder, &view);
+        encoder.copy_texture_to_buffer(
+            wgpu::TextureCopyView {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            wgpu::BufferCopyView {
+                buffer: &output_buffer,
+                layout: wgpu::TextureDataLayout {
+                    offset: 0,
+                    bytes_per_row: padded_bytes_per_row,
+                    rows_per_image: height,
+                },
+            },
+            size,
+        );
         self.queue.submit(iter::once(encoder.finish()));
 
-        Ok(())
+        let buffer_slice = output_buffer.slice(..);
+        let mapping = buffer_slice.map_async(wgpu::MapMode::Read);
+        self.device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(mapping)?;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = buffer_slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        output_buffer.unmap();
+        if bgra {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        image::RgbaImage::from_raw(width, height, pixels).context("Screenshot has wrong size")
     }
 }
 
+// Render one frame without opening a window, and save it
+//Command line settings that change State, for the window and screenshots alike
+#[derive(Debug, Default)]
+struct Options {
+    reach: Option<f32>,
+    fog: Option<FogMode>,
+    meshing: Option<mesher::MeshingMode>,
+    sky: Option<[f32; 3]>,
+    time: Option<f32>,
+    time_speed: Option<f32>,
+}
+
+impl Options {
+    //Invalid values are reported and left at their defaults
+    fn parse<F: Fn(&str) -> Option<String>>(arg_value: F) -> Self {
+        let mut options = Self::default();
+        if let Some(reach) = arg_value("--reach") {
+            match reach.parse::<f32>() {
+                Ok(reach) if reach.is_finite() && reach > 0.0 => options.reach = Some(reach),
+                _ => eprintln!("Invalid --reach {}, using {}", reach, DEFAULT_REACH),
+            }
+        }
+        if let Some(fog) = arg_value("--fog") {
+            options.fog = FogMode::parse(&fog);
+            if options.fog.is_none() {
+                eprintln!("Invalid --fog {}, use linear, exp or off", fog);
+            }
+        }
+        if let Some(meshing) = arg_value("--meshing") {
+            options.meshing = mesher::MeshingMode::parse(&meshing);
+            if options.meshing.is_none() {
+                eprintln!("Invalid --meshing {}, use greedy or naive", meshing);
+            }
+        }
+        if let Some(sky) = arg_value("--sky") {
+            let rgb: Vec<f32> = sky.split(',').filter_map(|c| c.trim().parse().ok()).collect();
+            if rgb.len() == 3 {
+                options.sky = Some([rgb[0], rgb[1], rgb[2]]);
+            } else {
+                eprintln!("Invalid --sky {}, use r,g,b from 0 to 1", sky);
+            }
+        }
+        //WorldClock::new wraps it into 0 to 24
+        if let Some(time) = arg_value("--time") {
+            match time.parse::<f32>() {
+                Ok(time) if time.is_finite() => options.time = Some(time),
+                _ => eprintln!("Invalid --time {}, use hours from 0 to 24", time),
+            }
+        }
+        if let Some(speed) = arg_value("--time-speed") {
+            match speed.parse::<f32>() {
+                Ok(speed) if speed.is_finite() => options.time_speed = Some(speed),
+                _ => eprintln!("Invalid --time-speed {}, using the saved or default speed", speed),
+            }
+        }
+        options
+    }
+
+    fn apply(&self, state: &mut State) {
+        if let Some(reach) = self.reach {
+            state.reach = reach;
+        }
+        if let Some(mode) = self.fog {
+            state.set_fog_mode(mode);
+        }
+        if let Some(mode) = self.meshing {
+            state.obj_model.set_meshing_mode(mode);
+        }
+        if let Some(sky) = self.sky {
+            state.set_sky_color(sky);
+        }
+        if let Some(time) = self.time {
+            state.clock = clock::WorldClock::new(time, state.clock.speed);
+        }
+        if let Some(speed) = self.time_speed {
+            state.clock.speed = speed;
+        }
+    }
+}
+
+fn save_screenshot(path: &Path, world_dir: Option<&Path>, options: &Options) -> anyhow::Result<()> {
+    use futures::executor::block_on;
+
+    let mut state = block_on(State::new_headless(SCREENSHOT_WIDTH, SCREENSHOT_HEIGHT, world_dir))?;
+    options.apply(&mut state);
+    state.update(std::time::Duration::from_secs(0));
+    let image = state.screenshot()?;
+    image
+        .save(path)
+        .with_context(|| format!("Unable to save screenshot to {}", path.display()))?;
+    println!("Saved screenshot to {}", path.display());
+    Ok(())
+}
+
+//Print the atlas tiles, their UV rectangles and the block faces using them
+fn list_tiles(world_dir: &Path) -> anyhow::Result<()> {
+    let registry = registry::BlockRegistry::for_world(world_dir)?;
+    let atlas = atlas::TextureAtlas::builtin(&registry)?;
+    println!("{} tiles", atlas.len());
+    for (name, rect, users) in atlas.defined_tiles() {
+        println!("{} [{}, {}, {}, {}] {}", name, rect.x, rect.y, rect.z, rect.w, users.join(", "));
+    }
+    Ok(())
+}
+
 fn main() {
     env_logger::init();
+
+    // kuberirust --screenshot out.png renders one frame headless and exits
+    // kuberirust --world dir plays the world saved in dir, default is ./world
+    let args: Vec<String> = std::env::args().collect();
+    // kuberirust --reach 8 lets you break and place blocks further away
+    // kuberirust --fog exp --sky 0.5,0.7,1.0 changes the fog (linear, exp or off) and the sky colour
+    // kuberirust --time 18 --time-speed 0 starts at 6 pm and stops the clock
+    // kuberirust --meshing naive draws one quad per block face instead of merging them
+    // kuberirust --list-tiles prints the tiles in the texture atlas and what uses them
+    let arg_value = |name: &str| {
+        args.iter()
+            .position(|a| a == name)
+            .and_then(|i| args.get(i + 1))
+            .cloned()
+    };
+    let world_dir = arg_value("--world").map(PathBuf::from);
+    if args.iter().any(|a| a == "--list-tiles") {
+        let dir = world_dir.unwrap_or_else(|| PathBuf::from(DEFAULT_WORLD_DIR));
+        if let Err(e) = list_tiles(&dir) {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    let options = Options::parse(arg_value);
+    if args.iter().any(|a| a == "--screenshot") {
+        let path = arg_value("--screenshot").unwrap_or_else(|| String::from("screenshot.png"));
+        if path.starts_with("--") {
+            eprintln!("Invalid --screenshot {}, give the output file right after it", path);
+            std::process::exit(1);
+        }
+        //Only show a saved world when asked to, the default is a freshly generated one
+        if let Err(e) = save_screenshot(Path::new(&path), world_dir.as_deref(), &options) {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let event_loop = EventLoop::new();
     let title = env!("CARGO_PKG_NAME");
     let window = winit::window::WindowBuilder::new()
@@ -374,8 +1152,13 @@ fn main() {
     use futures::executor::block_on;
 
     // Since main can't be async, we're going to need to block
-    let mut state = block_on(State::new(&window));
+    let world_dir = world_dir.unwrap_or_else(|| PathBuf::from(DEFAULT_WORLD_DIR));
+    let mut state = block_on(State::new(&window, Some(&world_dir)));
+    options.apply(&mut state);
+    //Hidden and kept in the window while interacting, so the mouse can be used to look around
+    let mut cursor_grabbed = false;
     let mut last_render_time = std::time::Instant::now();
+    let mut last_title_time = last_render_time;
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
@@ -386,6 +1169,13 @@ fn main() {
                 .. // We're not using device_id currently
             } => {
                 state.input(event);
+                if state.interact != cursor_grabbed {
+                    cursor_grabbed = state.interact;
+                    if let Err(e) = window.set_cursor_grab(cursor_grabbed) {
+                        eprintln!("Unable to grab cursor: {:?}", e);
+                    }
+                    window.set_cursor_visible(!cursor_grabbed);
+                }
             }
             // UPDATED!
             Event::WindowEvent {
@@ -394,16 +1184,15 @@ fn main() {
             } if window_id == window.id() => {
                 match event {
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::KeyboardInput { input, .. } => match input {
-                        KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode: Some(VirtualKeyCode::Escape),
-                            ..
-                        } => {
-                            *control_flow = ControlFlow::Exit;
-                        }
-                        _ => {}
-                    },
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::Escape),
+                                ..
+                            },
+                        ..
+                    } => *control_flow = ControlFlow::Exit,
                     WindowEvent::Resized(physical_size) => {
                         state.resize(*physical_size);
                     }
@@ -419,6 +1208,16 @@ fn main() {
                 let dt = now - last_render_time;
                 last_render_time = now;
                 state.update(dt);
+                if now - last_title_time > std::time::Duration::from_secs(1) {
+                    last_title_time = now;
+                    let stats = state.obj_model.cull_stats;
+                    let selected = &state.obj_model.world.registry.get(state.selected).name;
+                    let mode = if state.noclip { "flying" } else { "walking" };
+                    window.set_title(&format!(
+                        "{} - {}, placing {} - {} chunks drawn, {} culled",
+                        title, mode, selected, stats.drawn, stats.culled
+                    ));
+                }
                 match state.render() {
                     Ok(_) => {}
                     // Recreate the swap_chain if lost
@@ -429,6 +1228,11 @@ fn main() {
                     Err(e) => eprintln!("{:?}", e),
                 }
             }
+            Event::LoopDestroyed => {
+                if let Err(e) = state.save() {
+                    eprintln!("Unable to save world: {:?}", e);
+                }
+            }
             _ => {}
         }
     });
@@ -0,0 +1,440 @@
+use cgmath::{Vector2, Vector3, Vector4};
+
+use crate::atlas::TextureAtlas;
+use crate::model::{BlockRegistry, BlockType, Chunk, ChunkPos, ModelVertex, World};
+
+//Naive emits one quad per visible block face. Greedy merges neighbouring faces with the same tile and shading.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MeshingMode {
+    Naive,
+    Greedy,
+}
+
+impl MeshingMode {
+    //As written after --meshing
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "naive" => Some(MeshingMode::Naive),
+            "greedy" => Some(MeshingMode::Greedy),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Face {
+    Top,
+    Bottom,
+    Right,
+    Left,
+    Front,
+    Back,
+}
+
+pub const FACES: [Face; 6] = [
+    Face::Top,
+    Face::Bottom,
+    Face::Right,
+    Face::Left,
+    Face::Front,
+    Face::Back,
+];
+
+impl Face {
+    // top (0, 1, 0), bottom (0, -1, 0), right (1, 0, 0), left (-1, 0, 0), front (0, 0, 1), back (0, 0, -1)
+    pub fn normal(&self) -> [i32; 3] {
+        match self {
+            Face::Top => [0, 1, 0],
+            Face::Bottom => [0, -1, 0],
+            Face::Right => [1, 0, 0],
+            Face::Left => [-1, 0, 0],
+            Face::Front => [0, 0, 1],
+            Face::Back => [0, 0, -1],
+        }
+    }
+
+    //Corner of the unit cube where the face starts, and the directions of u and v as seen
+    //from outside the cube. Walking origin, +u, +u+v, +v is then counter clockwise.
+    pub fn axes(&self) -> ([i32; 3], [i32; 3], [i32; 3]) {
+        match self {
+            Face::Top => ([0, 1, 1], [1, 0, 0], [0, 0, -1]),
+            Face::Bottom => ([0, 0, 0], [1, 0, 0], [0, 0, 1]),
+            Face::Right => ([1, 0, 1], [0, 0, -1], [0, 1, 0]),
+            Face::Left => ([0, 0, 0], [0, 0, 1], [0, 1, 0]),
+            Face::Front => ([0, 0, 1], [1, 0, 0], [0, 1, 0]),
+            Face::Back => ([1, 0, 0], [-1, 0, 0], [0, 1, 0]),
+        }
+    }
+}
+
+//Chunk geometry on the CPU side, ready to be copied into wgpu buffers
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    //What the mesh takes in GPU buffers
+    pub fn size_in_bytes(&self) -> usize {
+        self.vertices.len() * std::mem::size_of::<ModelVertex>() + self.indices.len() * std::mem::size_of::<u32>()
+    }
+
+    //Add a quad covering width x height block faces, starting at the block whose face has corner (0, 0).
+    //The tile repeats once per block, tex_coords run from 0 to width/height.
+    //shade has the occlusion and light of the corners, in the same order as the vertices.
+    fn push_face(&mut self, first: [i32; 3], face: Face, tile: Vector4<f32>, width: i32, height: i32, shade: CornerShade) {
+        let (origin, u, v) = face.axes();
+        let corner = |du: i32, dv: i32| {
+            Vector3::new(
+                (first[0] + origin[0] + u[0] * du + v[0] * dv) as f32,
+                (first[1] + origin[1] + u[1] * du + v[1] * dv) as f32,
+                (first[2] + origin[2] + u[2] * du + v[2] * dv) as f32,
+            )
+        };
+        //Image rows go downwards, v goes up
+        let corners = [(0, 0), (width, 0), (width, height), (0, height)];
+        let n = face.normal();
+        let normal = Vector3::new(n[0] as f32, n[1] as f32, n[2] as f32);
+        let base = self.vertices.len() as u32;
+        let max = BlockRegistry::MAX_LIGHT as f32 * 4.0;
+        for (k, (du, dv)) in corners.iter().enumerate() {
+            self.vertices.push(ModelVertex {
+                position: corner(*du, *dv),
+                tex_coords: Vector2::new(*du as f32, (height - dv) as f32),
+                normal,
+                tile,
+                ao: shade.ao[k] as f32 / 3.0,
+                light: Vector2::new(shade.light[k][0] as f32 / max, shade.light[k][1] as f32 / max),
+            });
+        }
+        //Split along the brighter diagonal. With a fixed split, the same corner occlusion
+        //would shade differently depending on how the quad is turned.
+        let ao = shade.ao;
+        if ao[1] as u32 + ao[3] as u32 > ao[0] as u32 + ao[2] as u32 {
+            self.indices.extend_from_slice(&[base + 1, base + 2, base + 3, base + 3, base, base + 1]);
+        } else {
+            self.indices.extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+        }
+    }
+}
+
+//Block at chunk-local coordinates, which may be outside the chunk. Missing chunks count as air.
+fn block_at(world: &World, pos: ChunkPos, local: [i32; 3]) -> BlockType {
+    let size = world.chunk_size;
+    let blockpos = pos.to_world(local, size);
+    match world.chunks.get(&blockpos.chunk_pos(size)) {
+        Some(chunk) => chunk.get(blockpos.local(size)),
+        None => BlockType::AIR,
+    }
+}
+
+//Is the given face of the block at local covered by its neighbour? Opaque blocks hide everything,
+//transparent blocks only hide faces between two blocks of the same kind (e.g. inside a glass wall).
+fn is_hidden(world: &World, chunk: &Chunk, pos: ChunkPos, local: [i32; 3], face: Face) -> bool {
+    let n = face.normal();
+    let neighbour = [local[0] + n[0], local[1] + n[1], local[2] + n[2]];
+    let neighbour = if chunk.contains(neighbour) {
+        chunk.get(neighbour)
+    } else {
+        block_at(world, pos, neighbour)
+    };
+    world.registry.is_opaque(neighbour) || (neighbour != BlockType::AIR && neighbour == chunk.get(local))
+}
+
+//Does the block at local darken the corners next to it?
+fn is_occluder(world: &World, chunk: &Chunk, pos: ChunkPos, local: [i32; 3]) -> bool {
+    let blocktype = if chunk.contains(local) {
+        chunk.get(local)
+    } else {
+        block_at(world, pos, local)
+    };
+    world.registry.is_opaque(blocktype)
+}
+
+//Sky and block light at chunk-local coordinates. Missing chunks count as open sky.
+fn light_at(world: &World, chunk: &Chunk, pos: ChunkPos, local: [i32; 3]) -> [u8; 2] {
+    if chunk.contains(local) {
+        return [chunk.sky_light(local), chunk.block_light(local)];
+    }
+    let size = world.chunk_size;
+    let blockpos = pos.to_world(local, size);
+    match world.chunks.get(&blockpos.chunk_pos(size)) {
+        Some(other) => {
+            let local = blockpos.local(size);
+            [other.sky_light(local), other.block_light(local)]
+        }
+        None => [BlockRegistry::MAX_LIGHT, 0],
+    }
+}
+
+//How the corners of a face are lit, in push_face order
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+struct CornerShade {
+    //Ambient occlusion, from 0 (darkest) to 3 (open)
+    ao: [u8; 4],
+    //Sky and block light averaged over the blocks around the corner, in quarter levels (0-60)
+    light: [[u8; 2]; 4],
+}
+
+//Looks at the block in front of the face, and for each corner the two blocks beside it and the one
+//diagonally across it. Opaque ones darken the corner, the others give their light.
+fn face_shade(world: &World, chunk: &Chunk, pos: ChunkPos, local: [i32; 3], face: Face) -> CornerShade {
+    let n = face.normal();
+    let (_, u, v) = face.axes();
+    let at = |su: i32, sv: i32| {
+        let mut p = [0; 3];
+        for a in 0..3 {
+            p[a] = local[a] + n[a] + u[a] * su + v[a] * sv;
+        }
+        p
+    };
+    let mut shade = CornerShade {
+        ao: [3; 4],
+        light: [[0; 2]; 4],
+    };
+    for (k, (du, dv)) in [(-1, -1), (1, -1), (1, 1), (-1, 1)].iter().enumerate() {
+        let side1 = is_occluder(world, chunk, pos, at(*du, 0));
+        let side2 = is_occluder(world, chunk, pos, at(0, *dv));
+        let corner = is_occluder(world, chunk, pos, at(*du, *dv));
+        shade.ao[k] = if side1 && side2 {
+            0
+        } else {
+            3 - side1 as u8 - side2 as u8 - corner as u8
+        };
+
+        //The corner block can't be seen past two sides. The first count entries of lit are used.
+        let mut lit = [at(0, 0); 4];
+        let mut count = 1;
+        let neighbours = [
+            (at(*du, 0), !side1),
+            (at(0, *dv), !side2),
+            (at(*du, *dv), !(corner || side1 && side2)),
+        ];
+        for (p, visible) in neighbours.iter() {
+            if *visible {
+                lit[count] = *p;
+                count += 1;
+            }
+        }
+        for channel in 0..2 {
+            let sum: u32 = lit[..count].iter().map(|p| light_at(world, chunk, pos, *p)[channel] as u32).sum();
+            let count = count as u32;
+            shade.light[k][channel] = ((sum * 4 + count / 2) / count) as u8;
+        }
+    }
+    shade
+}
+
+pub fn build_mesh(world: &World, atlas: &TextureAtlas, pos: ChunkPos, mode: MeshingMode) -> MeshData {
+    match mode {
+        MeshingMode::Naive => mesh_chunk(world, atlas, pos),
+        MeshingMode::Greedy => mesh_chunk_greedy(world, atlas, pos),
+    }
+}
+
+//Build geometry for one chunk, leaving out faces hidden by a solid neighbour (also across chunk borders)
+pub fn mesh_chunk(world: &World, atlas: &TextureAtlas, pos: ChunkPos) -> MeshData {
+    let mut data = MeshData::default();
+    let chunk = match world.chunks.get(&pos) {
+        Some(chunk) => chunk,
+        None => return data,
+    };
+
+    for (local, blocktype) in chunk.iter_blocks() {
+        for face in FACES.iter() {
+            if !is_hidden(world, chunk, pos, local, *face) {
+                let tile = atlas.rect(atlas.face_tile(blocktype, *face));
+                let shade = face_shade(world, chunk, pos, local, *face);
+                data.push_face(local, *face, tile, 1, 1, shade);
+            }
+        }
+    }
+    data
+}
+
+//Index of the axis a direction runs along, and whether it runs the positive way
+fn axis(dir: [i32; 3]) -> (usize, bool) {
+    let a = dir.iter().position(|c| *c != 0).unwrap();
+    (a, dir[a] > 0)
+}
+
+//Same visible faces as mesh_chunk, but coplanar neighbours with the same tile and shading become one quad
+pub fn mesh_chunk_greedy(world: &World, atlas: &TextureAtlas, pos: ChunkPos) -> MeshData {
+    let mut data = MeshData::default();
+    let chunk = match world.chunks.get(&pos) {
+        Some(chunk) => chunk,
+        None => return data,
+    };
+    let size = chunk.size();
+    //Tile index and corner shading of each visible face
+    let mut mask: Vec<Option<(usize, CornerShade)>> = vec![None; (size * size) as usize];
+
+    for face in FACES.iter() {
+        let (_, u, v) = face.axes();
+        let (d, _) = axis(face.normal());
+        let (ua, u_positive) = axis(u);
+        let (va, v_positive) = axis(v);
+
+        for slice in 0..size {
+            //Visible faces in this slice. i runs along the u axis, j along the v axis, both increasing.
+            for j in 0..size {
+                for i in 0..size {
+                    let mut local = [0; 3];
+                    local[d] = slice;
+                    local[ua] = i;
+                    local[va] = j;
+                    let blocktype = chunk.get(local);
+                    mask[(j * size + i) as usize] =
+                        if blocktype != BlockType::AIR && !is_hidden(world, chunk, pos, local, *face) {
+                            Some((atlas.face_tile(blocktype, *face), face_shade(world, chunk, pos, local, *face)))
+                        } else {
+                            None
+                        };
+                }
+            }
+
+            for j in 0..size {
+                let mut i = 0;
+                while i < size {
+                    let quad = match mask[(j * size + i) as usize] {
+                        Some(q) => q,
+                        None => {
+                            i += 1;
+                            continue;
+                        }
+                    };
+                    let mut width = 1;
+                    while i + width < size && mask[(j * size + i + width) as usize] == Some(quad) {
+                        width += 1;
+                    }
+                    let mut height = 1;
+                    'grow: while j + height < size {
+                        for k in i..i + width {
+                            if mask[((j + height) * size + k) as usize] != Some(quad) {
+                                break 'grow;
+                            }
+                        }
+                        height += 1;
+                    }
+                    for jj in j..j + height {
+                        for k in i..i + width {
+                            mask[(jj * size + k) as usize] = None;
+                        }
+                    }
+
+                    //The quad starts at the block where the face's own u and v directions start
+                    let mut first = [0; 3];
+                    first[d] = slice;
+                    first[ua] = if u_positive { i } else { i + width - 1 };
+                    first[va] = if v_positive { j } else { j + height - 1 };
+                    let (tile, shade) = quad;
+                    data.push_face(first, *face, atlas.rect(tile), width, height, shade);
+                    i += width;
+                }
+            }
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worldgen::{TerrainGenerator, WorldGenerator};
+    use cgmath::InnerSpace;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    const SIZE: i32 = 16;
+
+    //Generated terrain around the chunk at the origin, with a few blocks placed on top
+    fn world() -> World {
+        let registry = Arc::new(BlockRegistry::builtin().unwrap());
+        let generator = TerrainGenerator::new(7, &registry).unwrap();
+        let mut world = World::new(SIZE, registry.clone());
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let pos = ChunkPos::new(x, y, z);
+                    world.chunks.insert(pos, generator.generate_chunk(pos, SIZE));
+                }
+            }
+        }
+        let chunk = world.chunks.get_mut(&ChunkPos::new(0, 0, 0)).unwrap();
+        chunk.set([3, 14, 3], registry.id("stone").unwrap());
+        chunk.set([4, 14, 3], registry.id("stone").unwrap());
+        chunk.set([0, 13, 0], registry.id("dirt").unwrap());
+        world
+    }
+
+    //Empty chunks at the origin and just below it on the x axis
+    fn empty_world() -> World {
+        let mut world = World::new(SIZE, Arc::new(BlockRegistry::builtin().unwrap()));
+        world.chunks.insert(ChunkPos::new(0, 0, 0), Chunk::new(SIZE));
+        world.chunks.insert(ChunkPos::new(-1, 0, 0), Chunk::new(SIZE));
+        world
+    }
+
+    //Total area of the quads facing each way. Every quad is four vertices.
+    fn area(data: &MeshData) -> HashMap<[i32; 3], f32> {
+        let mut area = HashMap::new();
+        for quad in data.vertices.chunks(4) {
+            let u = quad[1].position - quad[0].position;
+            let v = quad[3].position - quad[0].position;
+            let n = quad[0].normal;
+            *area.entry([n.x as i32, n.y as i32, n.z as i32]).or_insert(0.0) += u.cross(v).magnitude();
+        }
+        area
+    }
+
+    #[test]
+    fn greedy_covers_the_same_faces_with_fewer_vertices() {
+        let world = world();
+        let registry = world.registry.clone();
+        let atlas = TextureAtlas::builtin(&registry).unwrap();
+        for pos in [ChunkPos::new(0, 0, 0), ChunkPos::new(1, 0, -1), ChunkPos::new(0, -1, 0)].iter() {
+            let naive = build_mesh(&world, &atlas, *pos, MeshingMode::Naive);
+            let greedy = build_mesh(&world, &atlas, *pos, MeshingMode::Greedy);
+            assert!(!naive.is_empty());
+            assert!(greedy.vertices.len() <= naive.vertices.len(), "{:?}", pos);
+            assert_eq!(greedy.indices.len() / 6, greedy.vertices.len() / 4);
+            assert_eq!(area(&naive), area(&greedy), "{:?}", pos);
+        }
+    }
+
+    #[test]
+    fn faces_between_two_blocks_are_hidden() {
+        let mut world = empty_world();
+        let stone = world.registry.id("stone").unwrap();
+        let chunk = world.chunks.get_mut(&ChunkPos::new(0, 0, 0)).unwrap();
+        chunk.set([5, 5, 5], stone);
+        chunk.set([6, 5, 5], stone);
+        let atlas = TextureAtlas::builtin(&world.registry).unwrap();
+        let data = mesh_chunk(&world, &atlas, ChunkPos::new(0, 0, 0));
+        assert_eq!(data.vertices.len() / 4, 10);
+        assert_eq!(data.indices.len() / 6, 10);
+    }
+
+    #[test]
+    fn faces_against_a_neighbour_chunk_are_hidden() {
+        let mut world = empty_world();
+        let stone = world.registry.id("stone").unwrap();
+        world.chunks.get_mut(&ChunkPos::new(0, 0, 0)).unwrap().set([0, 5, 5], stone);
+        let atlas = TextureAtlas::builtin(&world.registry).unwrap();
+        let left = |data: &MeshData| data.vertices.iter().filter(|v| v.normal.x < 0.0).count() / 4;
+
+        let data = mesh_chunk(&world, &atlas, ChunkPos::new(0, 0, 0));
+        assert_eq!(data.vertices.len() / 4, 6);
+        assert_eq!(left(&data), 1);
+
+        world.chunks.get_mut(&ChunkPos::new(-1, 0, 0)).unwrap().set([SIZE - 1, 5, 5], stone);
+        let data = mesh_chunk(&world, &atlas, ChunkPos::new(0, 0, 0));
+        assert_eq!(data.vertices.len() / 4, 5);
+        assert_eq!(left(&data), 0);
+    }
+}
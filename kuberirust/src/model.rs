@@ -1,28 +1,45 @@
 use anyhow::*;
-use std::ops::Range;
 use wgpu::util::DeviceExt;
 
 use crate::texture;
-
-use rand::Rng;
-
-use std::collections::HashMap;
-
-use cgmath::Vector3;
-use cgmath::Vector2;
+use crate::atlas::{self, TextureAtlas};
+use crate::mesher::{self, MeshData, MeshingMode, FACES};
+use crate::worldgen::{TerrainGenerator, WorldGenerator};
+use crate::storage::WorldStorage;
+use crate::streaming::{ChunkManager, DEFAULT_VIEW_RADIUS, DEFAULT_VERTICAL_RADIUS};
+use crate::workers::{ChunkSource, Job, JobResult, WorkerPool};
+use crate::frustum::Frustum;
+use crate::player::Aabb;
+pub use crate::chunk::Chunk;
+pub use crate::registry::{BlockRegistry, BlockType};
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
 
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a>;
 }
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ModelVertex {
-    position: cgmath::Vector3<f32>,
-    tex_coords: cgmath::Vector2<f32>,
-    //normal: cgmath::Vector3<f32>,
+    pub position: cgmath::Vector3<f32>,
+    //Position inside the tile, in tiles. Repeats across quads merged by the greedy mesher.
+    pub tex_coords: cgmath::Vector2<f32>,
+    //Pointing out of the face, for lighting
+    pub normal: cgmath::Vector3<f32>,
     //tangent: cgmath::Vector3<f32>,
     //bitangent: cgmath::Vector3<f32>,
+    //Tile in the atlas: u, v of top left corner, then width and height
+    pub tile: cgmath::Vector4<f32>,
+    //Ambient occlusion, 0 in a corner between blocks and 1 out in the open
+    pub ao: f32,
+    //Sky light and block light, 0 to 1
+    pub light: cgmath::Vector2<f32>,
 }
 
 unsafe impl bytemuck::Zeroable for ModelVertex {}
@@ -45,12 +62,27 @@ impl Vertex for ModelVertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float2,
                 },
-                /*
                 wgpu::VertexAttributeDescriptor {
                     offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
                     shader_location: 2,
                     format: wgpu::VertexFormat::Float3,
                 },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 13]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float2,
+                },
+                /*
                 // Tangent and bitangent
                 wgpu::VertexAttributeDescriptor {
                     offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
@@ -68,7 +100,9 @@ impl Vertex for ModelVertex {
     }
 }
 
+//Holds on to the texture for as long as the bind group using it
 #[derive(Debug)]
+#[allow(dead_code)]
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
@@ -108,70 +142,228 @@ impl Material {
     }
 }
 
+//Geometry of one chunk. Vertices are chunk-local, the single instance moves them into place.
 #[derive(Debug)]
 pub struct Mesh {
-    pub blocktype: BlockType,
+    pub chunk_pos: ChunkPos,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_indexes: u32,
-    pub instances: Vec<Instance>,
     pub instances_buffer: wgpu::Buffer,
     pub num_instances: u32,
+    //Around the vertices, in world space
+    pub bounds: Aabb,
+    //Room in the buffers, so a remeshed chunk can reuse them
+    vertex_capacity: usize,
+    index_capacity: usize,
+}
+
+impl Mesh {
+    pub fn new(device: &wgpu::Device, chunk_pos: ChunkPos, chunk_size: i32, data: &MeshData) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(&data.vertices),
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(&data.indices),
+            usage: wgpu::BufferUsage::INDEX | wgpu::BufferUsage::COPY_DST,
+        });
+
+        let instance = Instance { position: chunk_pos.origin(chunk_size).to_vec3() };
+        let instances_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instance Buffer"),
+            contents: bytemuck::cast_slice(&[instance.to_raw()]),
+            usage: wgpu::BufferUsage::VERTEX,
+        });
+
+        Self {
+            chunk_pos,
+            vertex_buffer,
+            index_buffer,
+            num_indexes: data.indices.len() as u32,
+            instances_buffer,
+            num_instances: 1,
+            bounds: Self::bounds(chunk_pos, chunk_size, data),
+            vertex_capacity: data.vertices.len(),
+            index_capacity: data.indices.len(),
+        }
+    }
+
+    //World space box around the vertices, for frustum culling
+    fn bounds(chunk_pos: ChunkPos, chunk_size: i32, data: &MeshData) -> Aabb {
+        let origin = Point3::from_vec(chunk_pos.origin(chunk_size).to_vec3());
+        let mut min = Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for vertex in data.vertices.iter() {
+            let p = origin + vertex.position;
+            min = Point3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Point3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        Aabb::new(min, max)
+    }
+
+    //New geometry for the same chunk. Writes into the existing buffers if it fits.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, chunk_size: i32, data: &MeshData) {
+        if data.vertices.len() > self.vertex_capacity || data.indices.len() > self.index_capacity {
+            *self = Self::new(device, self.chunk_pos, chunk_size, data);
+            return;
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&data.vertices));
+        self.bounds = Self::bounds(self.chunk_pos, chunk_size, data);
+        queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&data.indices));
+        self.num_indexes = data.indices.len() as u32;
+    }
 }
 
 #[derive(Debug)]
 pub struct World{
-    pub chunks: HashMap<[u8;3], Chunk>,
+    pub chunk_size: i32,
+    pub chunks: HashMap<ChunkPos, Chunk>,
+    pub registry: Arc<BlockRegistry>,
+    //Chunks changed by set_block whose mesh is out of date
+    pub dirty: HashSet<ChunkPos>,
+    //Chunks changed by set_block since they were loaded or last saved
+    pub modified: HashSet<ChunkPos>,
 }
 
+impl World {
+    pub fn new(chunk_size: i32, registry: Arc<BlockRegistry>) -> Self {
+        Self { chunk_size, chunks: HashMap::new(), registry, dirty: HashSet::new(), modified: HashSet::new() }
+    }
+
+    //None if the chunk holding pos isn't loaded
+    pub fn get_block(&self, pos: BlockPos) -> Option<BlockType> {
+        self.chunks
+            .get(&pos.chunk_pos(self.chunk_size))
+            .map(|chunk| chunk.get(pos.local(self.chunk_size)))
+    }
+
+    //Returns the block that was replaced. Does nothing and returns None if the chunk isn't loaded.
+    pub fn set_block(&mut self, pos: BlockPos, blocktype: BlockType) -> Option<BlockType> {
+        let size = self.chunk_size;
+        let chunk_pos = pos.chunk_pos(size);
+        let local = pos.local(size);
+        let chunk = self.chunks.get_mut(&chunk_pos)?;
+        let old = chunk.get(local);
+        if old == blocktype {
+            return Some(old);
+        }
+        chunk.set(local, blocktype);
+        self.modified.insert(chunk_pos);
+
+        let seeing = self.chunks_seeing(pos);
+        self.dirty.extend(seeing);
+        let relit = self.relight_block(pos, old);
+        self.dirty.extend(relit);
+        Some(old)
+    }
+
+    //Loaded chunks whose meshes depend on the block: its own chunk and the ones sharing a face,
+    //edge or corner with it
+    pub fn chunks_seeing(&self, pos: BlockPos) -> Vec<ChunkPos> {
+        let size = self.chunk_size;
+        let chunk_pos = pos.chunk_pos(size);
+        let local = pos.local(size);
+        let near = |c: i32| if c == 0 { -1..=0 } else if c == size - 1 { 0..=1 } else { 0..=0 };
+        let mut seeing = Vec::new();
+        for dx in near(local[0]) {
+            for dy in near(local[1]) {
+                for dz in near(local[2]) {
+                    let neighbour = chunk_pos.offset(dx, dy, dz);
+                    if self.chunks.contains_key(&neighbour) {
+                        seeing.push(neighbour);
+                    }
+                }
+            }
+        }
+        seeing
+    }
 
-#[derive(PartialEq, Eq, Hash, Copy, Clone)]
-pub enum QuadType {
-    GRASS_TOP,
-    GRASS_SIDE,
-    DIRT,
-    STONE,
+    //Replace with air. Returns the removed block, None if the chunk isn't loaded.
+    pub fn remove_block(&mut self, pos: BlockPos) -> Option<BlockType> {
+        self.set_block(pos, BlockType::AIR)
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-pub enum BlockType {
-    GRASS,
-    DIRT,
-    STONE,
+//Position of a chunk in chunk units. Chunk (1,0,0) starts at block (chunk_size,0,0).
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Default)]
+pub struct ChunkPos {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
 }
 
-#[derive(Debug)]
-pub struct Block {
-    pub blocktype : BlockType,
+impl ChunkPos {
+    pub fn new(x: i32, y: i32, z: i32) -> Self {
+        Self { x, y, z }
+    }
+
+    //World position of the chunk's block with local coordinates (0,0,0)
+    pub fn origin(&self, chunk_size: i32) -> BlockPos {
+        BlockPos::new(self.x * chunk_size, self.y * chunk_size, self.z * chunk_size)
+    }
+
+    //Chunk-local to world coordinates
+    pub fn to_world(self, local: [i32; 3], chunk_size: i32) -> BlockPos {
+        let origin = self.origin(chunk_size);
+        BlockPos::new(origin.x + local[0], origin.y + local[1], origin.z + local[2])
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32, dz: i32) -> Self {
+        Self::new(self.x + dx, self.y + dy, self.z + dz)
+    }
 }
 
-#[derive(Debug)]
-pub struct Chunk {
-    pub blocks: HashMap<[u8;3], Block>,
+//Position of a block in world units.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Default)]
+pub struct BlockPos {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
 }
 
-const CHUNKSIZE: u8 = 3;
+impl BlockPos {
+    pub fn new(x: i32, y: i32, z: i32) -> Self {
+        Self { x, y, z }
+    }
 
-#[derive(PartialEq, Eq, Hash)]
-pub enum UV {
-    MIN,
-    MAX,
-}
+    //The block containing a point in world space, e.g. the camera position
+    pub fn containing(point: cgmath::Point3<f32>) -> Self {
+        Self::new(point.x.floor() as i32, point.y.floor() as i32, point.z.floor() as i32)
+    }
+
+    //World to chunk coordinates. Rounds down, plain / would put block -1 in chunk 0.
+    pub fn chunk_pos(&self, chunk_size: i32) -> ChunkPos {
+        ChunkPos::new(
+            self.x.div_euclid(chunk_size),
+            self.y.div_euclid(chunk_size),
+            self.z.div_euclid(chunk_size),
+        )
+    }
+
+    //Coordinates inside the chunk, always in 0..chunk_size
+    pub fn local(&self, chunk_size: i32) -> [i32; 3] {
+        [
+            self.x.rem_euclid(chunk_size),
+            self.y.rem_euclid(chunk_size),
+            self.z.rem_euclid(chunk_size),
+        ]
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32, dz: i32) -> Self {
+        Self::new(self.x + dx, self.y + dy, self.z + dz)
+    }
 
-#[derive(PartialEq, Eq, Hash)]
-pub struct UVQuadKey{
-    quadtype: QuadType,
-    uv: UV
+    pub fn to_vec3(self) -> Vector3<f32> {
+        Vector3::new(self.x as f32, self.y as f32, self.z as f32)
+    }
 }
 
-const CUBE_INDICES: &[u16] = &[
-    0, 1, 2, 2, 3, 0, // top
-    4, 5, 6, 6, 7, 4, // bottom
-    8, 9, 10, 10, 11, 8, // right
-    12, 13, 14, 14, 15, 12, // left
-    16, 17, 18, 18, 19, 16, // front
-    20, 21, 22, 22, 23, 20, // back
-];
+
+//Default chunk size. Use World::new for other sizes.
+pub const CHUNKSIZE: i32 = 16;
 
 #[derive(Debug)]
 pub struct Instance {
@@ -233,159 +425,94 @@ impl InstanceRaw {
     }
 }
 
+//Seed used when none is given
+pub const DEFAULT_SEED: u64 = 0x6b75_6265;
+
 #[derive(Debug)]
 pub struct Model {
-    pub meshes: Vec<Mesh>,
+    pub meshes: HashMap<ChunkPos, Mesh>,
     pub material: Option<Material>,
     pub world : World,
+    pub meshing_mode: MeshingMode,
+    pub atlas: Arc<TextureAtlas>,
+    //Where chunks are loaded from and saved to. None for worlds that only live in memory.
+    pub storage: Option<WorldStorage>,
+    pub chunk_manager: ChunkManager,
+    //Chunks are loaded and meshed on these threads
+    workers: WorkerPool,
+    //Chunks to mesh on the workers, because a neighbour was loaded or unloaded
+    remesh: HashSet<ChunkPos>,
+    //Jobs submitted to the workers and not finished yet
+    loading: HashSet<ChunkPos>,
+    meshing: HashSet<ChunkPos>,
+    //Chunks rebuilt on the main thread while the workers were meshing them. Their worker mesh is older.
+    outdated: HashSet<ChunkPos>,
+    //Chunks that could not be loaded. Not retried until they have been out of range.
+    failed: HashSet<ChunkPos>,
+    //Meshes built by the workers, waiting to be uploaded
+    finished: HashMap<ChunkPos, MeshData>,
+    //Meshes in view at the last cull. Only these are drawn.
+    visible: Vec<ChunkPos>,
+    pub cull_stats: CullStats,
+    //Meshes in the light's view at the last cull_shadows. Drawn into the shadow map.
+    shadow_casters: Vec<ChunkPos>,
+}
 
+//Meshes drawn and skipped by the last Model::cull
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CullStats {
+    pub drawn: usize,
+    pub culled: usize,
 }
 
 impl Model {
-    fn build_random_chunk(&self)->Chunk
-    {
-        //Generate random chunk
-        let mut chunk = Chunk{ blocks : HashMap::new(),};
-        let mut rng = rand::thread_rng();
-        for k in 0..CHUNKSIZE {
-            for l in 0..CHUNKSIZE {
-                for m in 0..CHUNKSIZE {
-                    let val = rng.gen_range(0, 10);
-                    if val < 3
-                    {
-                        //Add block
-                        chunk.blocks.insert( [k, l, m], Block{blocktype:BlockType::GRASS});
-                    }
-                    else if val < 4
-                    {
-                        chunk.blocks.insert( [k, l, m], Block{blocktype:BlockType::STONE});
-                    }
-                }
-            }
-        }
-        chunk
-    }
-
-
-    fn create_vertices(&self, blocktype:BlockType) -> Vec<ModelVertex>{
-        //Build ModelVertex. Have to lookup u and v wich is dependent on QuadType. (this decides where to find in correct bitmap in blockatlas.jpg)
-        //TODO: move umap and vmap outside function and convert to closure
-        fn build_vertex(position:[i8;3], quadtype:QuadType, u:UV, v:UV)->ModelVertex
-        {
-            let mut umap: HashMap<UVQuadKey, f32> = HashMap::new();
-            umap.insert(UVQuadKey{quadtype:QuadType::GRASS_TOP, uv:UV::MIN}, 0.125); umap.insert(UVQuadKey{quadtype:QuadType::GRASS_TOP, uv:UV::MAX}, 0.1875);
-            umap.insert(UVQuadKey{quadtype:QuadType::GRASS_SIDE, uv:UV::MIN}, 0.1875); umap.insert(UVQuadKey{quadtype:QuadType::GRASS_SIDE, uv:UV::MAX}, 0.25);
-            umap.insert(UVQuadKey{quadtype:QuadType::DIRT, uv:UV::MIN}, 0.125); umap.insert(UVQuadKey{quadtype:QuadType::DIRT, uv:UV::MAX}, 0.1875);
-            umap.insert(UVQuadKey{quadtype:QuadType::STONE, uv:UV::MIN}, 0.0); umap.insert(UVQuadKey{quadtype:QuadType::STONE, uv:UV::MAX}, 0.0625);
-        
-            let mut vmap: HashMap<UVQuadKey, f32> = HashMap::new();
-            vmap.insert(UVQuadKey{quadtype:QuadType::GRASS_TOP, uv:UV::MIN}, 0.375); vmap.insert(UVQuadKey{quadtype:QuadType::GRASS_TOP, uv:UV::MAX}, 0.4375);
-            vmap.insert(UVQuadKey{quadtype:QuadType::GRASS_SIDE, uv:UV::MIN}, 0.9375); vmap.insert(UVQuadKey{quadtype:QuadType::GRASS_SIDE, uv:UV::MAX}, 1.0);
-            vmap.insert(UVQuadKey{quadtype:QuadType::DIRT, uv:UV::MIN}, 0.9375); vmap.insert(UVQuadKey{quadtype:QuadType::DIRT, uv:UV::MAX}, 1.0);
-            vmap.insert(UVQuadKey{quadtype:QuadType::STONE, uv:UV::MIN}, 0.875); vmap.insert(UVQuadKey{quadtype:QuadType::STONE, uv:UV::MAX}, 0.9375);
-          
-            let u_pos = umap.get(&UVQuadKey{quadtype:quadtype, uv:u});
-            match u_pos {
-                Some(i) => {
-                    let v_pos = vmap.get(&UVQuadKey{quadtype:quadtype, uv:v});
-                    match v_pos {
-                        Some(j) => {
-                            let pos = Vector3::new(position[0] as f32, position[1] as f32, position[2] as f32);
-                            let tex = Vector2::new(Clone::clone(u_pos.unwrap()), 1.0-Clone::clone(v_pos.unwrap()));
-                            ModelVertex{position:pos, tex_coords:tex}
-                        },
-                        None => panic!("Key not found in vmap."),
-                    }
-                },
-                None => panic!("Key not found in umap."),
-            }
-        }
-    
-    
-        let mut quadtype:QuadType=QuadType::STONE;
-        if blocktype == BlockType::DIRT
-        {
-            quadtype = QuadType::DIRT;
-        }
-        
-        let mut vertex_data: Vec<ModelVertex>= Vec::new();
-        
-        // top (0, 0, 1)
-        let mut temp_quadtype:QuadType=quadtype;   
-        if blocktype==BlockType::GRASS
-        {
-            temp_quadtype = QuadType::GRASS_TOP;
-        }
-    
-        vertex_data.push(build_vertex([0, 0, 1], temp_quadtype, UV::MIN, UV::MIN));
-        vertex_data.push(build_vertex([1, 0, 1], temp_quadtype, UV::MAX, UV::MIN));
-        vertex_data.push(build_vertex([1, 1, 1], temp_quadtype, UV::MAX, UV::MAX));
-        vertex_data.push(build_vertex([0, 1, 1], temp_quadtype, UV::MIN, UV::MAX));
-    
-        // bottom (0, 0, -1) 
-        temp_quadtype=quadtype;   
-        if blocktype==BlockType::GRASS
-        {
-            temp_quadtype = QuadType::DIRT;
-        }
-    
-        vertex_data.push(build_vertex([0, 1, 0], temp_quadtype, UV::MAX, UV::MIN));
-        vertex_data.push(build_vertex([1, 1, 0], temp_quadtype, UV::MIN, UV::MIN));
-        vertex_data.push(build_vertex([1, 0, 0], temp_quadtype, UV::MIN, UV::MAX));
-        vertex_data.push(build_vertex([0, 0, 0], temp_quadtype, UV::MAX, UV::MAX));
-    
-        // right (1, 0, 0)
-        temp_quadtype=quadtype;   
-        if blocktype==BlockType::GRASS
-        {
-            temp_quadtype = QuadType::GRASS_SIDE;
-        }
-        vertex_data.push(build_vertex([1, 0, 0], temp_quadtype, UV::MIN, UV::MIN));
-        vertex_data.push(build_vertex([1, 1, 0], temp_quadtype, UV::MAX, UV::MIN));
-        vertex_data.push(build_vertex([1, 1, 1], temp_quadtype, UV::MAX, UV::MAX));
-        vertex_data.push(build_vertex([1, 0, 1], temp_quadtype, UV::MIN, UV::MAX));
-    
-        // left (-1, 0, 0)
-        temp_quadtype=quadtype;   
-        if blocktype==BlockType::GRASS
-        {
-            temp_quadtype = QuadType::GRASS_SIDE;
-        }
-    
-        vertex_data.push(build_vertex([0, 0, 1], temp_quadtype, UV::MIN, UV::MAX));
-        vertex_data.push(build_vertex([0, 1, 1], temp_quadtype, UV::MAX, UV::MAX));
-        vertex_data.push(build_vertex([0, 1, 0], temp_quadtype, UV::MAX, UV::MIN));
-        vertex_data.push(build_vertex([0, 0, 0], temp_quadtype, UV::MIN, UV::MIN));
-    
-        // front (0, 1, 0)
-        temp_quadtype=quadtype;   
-        if blocktype==BlockType::GRASS
-        {
-            temp_quadtype = QuadType::GRASS_SIDE;
-        }
-    
-        vertex_data.push(build_vertex([1, 1, 0], temp_quadtype, UV::MAX, UV::MIN));
-        vertex_data.push(build_vertex([0, 1, 0], temp_quadtype, UV::MIN, UV::MIN));
-        vertex_data.push(build_vertex([0, 1, 1], temp_quadtype, UV::MIN, UV::MAX));
-        vertex_data.push(build_vertex([1, 1, 1], temp_quadtype, UV::MAX, UV::MAX));
-    
-        // back (0, -1, 0)
-        temp_quadtype=quadtype;   
-        if blocktype==BlockType::GRASS
-        {
-            temp_quadtype = QuadType::GRASS_SIDE;
-        }
-    
-        vertex_data.push(build_vertex([1, 0, 1], temp_quadtype, UV::MAX, UV::MAX));
-        vertex_data.push(build_vertex([0, 0, 1], temp_quadtype, UV::MIN, UV::MAX));
-        vertex_data.push(build_vertex([0, 0, 0], temp_quadtype, UV::MIN, UV::MIN));
-        vertex_data.push(build_vertex([1, 0, 0], temp_quadtype, UV::MAX, UV::MIN));
-    
-        vertex_data
-    }
-    
     pub fn new()-> Result<Self>{
-        Ok(Self { meshes: Vec::new(), material:None, world: World{chunks:HashMap::new()} })
+        let registry = Arc::new(BlockRegistry::builtin()?);
+        let generator = TerrainGenerator::new(DEFAULT_SEED, &registry)?;
+        Self::with_generator(registry, Box::new(generator))
+    }
+
+    //World saved in dir, or a new one if there is nothing there yet. Uses the blocks in
+    //dir/blocks.ron if there is one.
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let registry = Arc::new(BlockRegistry::for_world(&dir)?);
+        let storage = WorldStorage::open(dir, registry.clone())?;
+        let generator = TerrainGenerator::new(storage.meta.seed, &registry)?;
+        Self::build(registry, Arc::new(generator), Some(storage))
+    }
+
+    pub fn with_generator(registry: Arc<BlockRegistry>, generator: Box<dyn WorldGenerator>)-> Result<Self>{
+        Self::build(registry, Arc::from(generator), None)
+    }
+
+    fn build(registry: Arc<BlockRegistry>, generator: Arc<dyn WorldGenerator>, storage: Option<WorldStorage>) -> Result<Self> {
+        let atlas = Arc::new(TextureAtlas::builtin(&registry)?);
+        let chunk_size = storage.as_ref().map(|s| s.meta.chunk_size).unwrap_or(CHUNKSIZE);
+        let workers = WorkerPool::new(ChunkSource {
+            generator,
+            storage: storage.clone(),
+            atlas: atlas.clone(),
+            chunk_size,
+        });
+        Ok(Self {
+            meshes: HashMap::new(),
+            material: None,
+            world: World::new(chunk_size, registry),
+            meshing_mode: MeshingMode::Greedy,
+            atlas,
+            storage,
+            chunk_manager: ChunkManager::new(DEFAULT_VIEW_RADIUS, DEFAULT_VERTICAL_RADIUS),
+            workers,
+            remesh: HashSet::new(),
+            loading: HashSet::new(),
+            meshing: HashSet::new(),
+            outdated: HashSet::new(),
+            failed: HashSet::new(),
+            finished: HashMap::new(),
+            visible: Vec::new(),
+            shadow_casters: Vec::new(),
+            cull_stats: CullStats::default(),
+        })
     }
 
     pub fn load(
@@ -393,11 +520,10 @@ impl Model {
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         layout: &wgpu::BindGroupLayout,
-    ){
+    ) -> Result<()> {
         //load material
-        let diffuse_bytes = include_bytes!("blockatlas.jpg");
         let diffuse_texture =
-            texture::Texture::from_bytes(&device, &queue, diffuse_bytes, "blockatlas.jpg").unwrap();
+            texture::Texture::from_bytes(device, queue, atlas::ATLAS_PNG, "blockatlas.png")?;
         self.material = Some(Material::new(
             device,
             "blockatlas",
@@ -405,77 +531,290 @@ impl Model {
             //normal_texture,
             layout,
         ));
-        
-        //build world
-        //First chunk,
-        //trenger flere sef
-        self.world.chunks.insert( [0, 0, 0], self.build_random_chunk());
-
-        //Go through world and build meshes. One mesh for each blocktype
-        let mut create_mesh_and_addto_model = |blocktype| {
-            let create_instance = |x, y, z| {
-                let position = cgmath::Vector3 {
-                    x: x as f32,
-                    y: y as f32,
-                    z: z as f32,
+        Ok(())
+    }
+
+    //Mesh with mode from now on. Loaded chunks are meshed again on the workers.
+    pub fn set_meshing_mode(&mut self, mode: MeshingMode) {
+        if mode == self.meshing_mode {
+            return;
+        }
+        self.meshing_mode = mode;
+        //Meshes built or being built the old way are thrown away
+        self.finished.clear();
+        self.outdated.extend(self.meshing.iter().cloned());
+        self.remesh.extend(self.world.chunks.keys().cloned());
+    }
+
+    //Load and mesh everything in view of position, waiting for the workers. For screenshots and tests.
+    pub fn load_around(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, position: Point3<f32>) -> Result<()> {
+        loop {
+            self.stream_chunks(device, queue, position, usize::MAX)?;
+            if self.loading.is_empty() && self.meshing.is_empty() {
+                return Ok(());
+            }
+            let result = self.workers.recv();
+            self.finish_job(result);
+        }
+    }
+
+    //Call every frame before render. Never waits for the workers, uploads at most
+    //chunk_manager.upload_bytes_per_frame of streamed chunks. Edited chunks are rebuilt right away.
+    pub fn update_chunks(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, position: Point3<f32>) -> Result<()> {
+        let budget = self.chunk_manager.upload_bytes_per_frame;
+        self.stream_chunks(device, queue, position, budget)?;
+        self.rebuild_dirty(device, queue);
+        Ok(())
+    }
+
+    //Mesh the chunks changed by World::set_block on this thread, so edits show up in the next frame.
+    //There are only a few of them, unlike chunks streamed in.
+    pub fn rebuild_dirty(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let dirty: Vec<ChunkPos> = self.world.dirty.drain().collect();
+        for pos in dirty {
+            if !self.world.chunks.contains_key(&pos) {
+                continue;
+            }
+            if !self.neighbours_loaded(pos) {
+                //Meshed when the neighbours arrive
+                self.remesh.insert(pos);
+                continue;
+            }
+            if self.meshing.contains(&pos) {
+                self.outdated.insert(pos);
+            }
+            self.remesh.remove(&pos);
+            self.finished.remove(&pos);
+            let data = mesher::build_mesh(&self.world, &self.atlas, pos, self.meshing_mode);
+            self.upload_mesh(device, queue, pos, data);
+        }
+    }
+
+    fn stream_chunks(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        position: Point3<f32>,
+        upload_budget: usize,
+    ) -> Result<()> {
+        let center = BlockPos::containing(position).chunk_pos(self.world.chunk_size);
+        if self.chunk_manager.set_center(center) {
+            self.unload_out_of_range()?;
+            let manager = &self.chunk_manager;
+            let cancelled = self.workers.set_center(center, |pos| manager.in_range(pos));
+            for job in cancelled {
+                match job {
+                    Job::Load(pos) => self.loading.remove(&pos),
+                    Job::Mesh(pos, _, _) => {
+                        self.outdated.remove(&pos);
+                        self.meshing.remove(&pos)
+                    }
                 };
-                Instance { position }
-            };
-
-            let mut instances=Vec::new();
-            for (chunkkey, chunk) in &self.world.chunks {
-                for (blockkey, block) in &chunk.blocks {
-                    if block.blocktype == blocktype
-                    {
-                        //transler til rett plass. Må ta hensyn til flere chunks.
-                        let x = (chunkkey[0] * CHUNKSIZE ) + blockkey[0];
-                        let y = (chunkkey[1] * CHUNKSIZE ) + blockkey[1];
-                        let z = (chunkkey[2] * CHUNKSIZE ) + blockkey[2];
-
-                        instances.push(create_instance(x as f32, y as f32, z as f32));
+            }
+        }
+
+        while let Some(result) = self.workers.try_recv() {
+            self.finish_job(result);
+        }
+        self.request_chunks();
+        self.request_meshes();
+        self.upload_meshes(device, queue, center, upload_budget);
+        Ok(())
+    }
+
+    //Drop chunks the camera has moved away from, saving the ones that were changed
+    fn unload_out_of_range(&mut self) -> Result<()> {
+        let gone: Vec<ChunkPos> = self
+            .world
+            .chunks
+            .keys()
+            .filter(|pos| !self.chunk_manager.in_range(**pos))
+            .cloned()
+            .collect();
+        if let Some(storage) = &self.storage {
+            let world = &self.world;
+            let changed = gone.iter().filter(|pos| world.modified.contains(pos));
+            storage.save_chunks(changed.map(|pos| (*pos, &world.chunks[pos])))?;
+        }
+        for pos in gone {
+            self.world.chunks.remove(&pos);
+            self.world.dirty.remove(&pos);
+            self.world.modified.remove(&pos);
+            self.remesh.remove(&pos);
+            self.meshes.remove(&pos);
+            self.finished.remove(&pos);
+            self.mark_neighbours_dirty(pos);
+        }
+        let manager = &self.chunk_manager;
+        self.failed.retain(|pos| manager.in_range(*pos));
+        Ok(())
+    }
+
+    fn finish_job(&mut self, result: JobResult) {
+        match result {
+            JobResult::Loaded(pos, chunk) => {
+                self.loading.remove(&pos);
+                if !self.chunk_manager.in_range(pos) || self.world.chunks.contains_key(&pos) {
+                    return;
+                }
+                if let Err(e) = &chunk {
+                    eprintln!("Unable to load chunk {:?}: {:?}", pos, e);
+                    self.failed.insert(pos);
+                    return;
+                }
+                self.world.chunks.insert(pos, chunk.unwrap());
+                //Light reaching into the neighbours changes their meshes too
+                let relit = self.world.light_new_chunk(pos);
+                self.remesh.extend(relit);
+                self.mark_neighbours_dirty(pos);
+            }
+            JobResult::Meshed(pos, data) => {
+                self.meshing.remove(&pos);
+                if self.outdated.remove(&pos) {
+                    return;
+                }
+                if let Err(e) = &data {
+                    eprintln!("Unable to mesh chunk {:?}: {:?}", pos, e);
+                    return;
+                }
+                if self.world.chunks.contains_key(&pos) {
+                    self.finished.insert(pos, data.unwrap());
+                }
+            }
+        }
+    }
+
+    //Ask the workers for every chunk in range that isn't loaded or on its way
+    fn request_chunks(&mut self) {
+        for pos in self.chunk_manager.wanted() {
+            if !self.world.chunks.contains_key(pos) && !self.loading.contains(pos) && !self.failed.contains(pos) {
+                self.loading.insert(*pos);
+                self.workers.submit(Job::Load(*pos));
+            }
+        }
+    }
+
+    //Faces on the border to pos may have become hidden or visible
+    fn mark_neighbours_dirty(&mut self, pos: ChunkPos) {
+        for face in FACES.iter() {
+            let n = face.normal();
+            let neighbour = pos.offset(n[0], n[1], n[2]);
+            if self.world.chunks.contains_key(&neighbour) {
+                self.remesh.insert(neighbour);
+            }
+        }
+    }
+
+    //Neighbours in range are loaded, so the chunk's borders can be meshed for good
+    fn neighbours_loaded(&self, pos: ChunkPos) -> bool {
+        FACES.iter().all(|face| {
+            let n = face.normal();
+            let neighbour = pos.offset(n[0], n[1], n[2]);
+            self.world.chunks.contains_key(&neighbour)
+                || !self.chunk_manager.in_range(neighbour)
+                || self.failed.contains(&neighbour)
+        })
+    }
+
+    //Send chunks waiting for a mesh to the workers. A chunk that needs a new mesh while being meshed
+    //is sent again when the first mesh is done.
+    fn request_meshes(&mut self) {
+        let ready: Vec<ChunkPos> = self
+            .remesh
+            .iter()
+            .filter(|pos| {
+                !self.meshing.contains(pos) && self.world.chunks.contains_key(pos) && self.neighbours_loaded(**pos)
+            })
+            .cloned()
+            .collect();
+        for pos in ready {
+            self.remesh.remove(&pos);
+            self.meshing.insert(pos);
+            self.workers.submit(Job::Mesh(pos, self.snapshot(pos), self.meshing_mode));
+        }
+    }
+
+    //Copy of the chunk and the loaded chunks around it, all the mesher looks at
+    fn snapshot(&self, pos: ChunkPos) -> World {
+        let mut world = World::new(self.world.chunk_size, self.world.registry.clone());
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let p = pos.offset(dx, dy, dz);
+                    if let Some(chunk) = self.world.chunks.get(&p) {
+                        world.chunks.insert(p, chunk.clone());
                     }
                 }
             }
-            //println!("gvtest instances: {:?}", instances);
-            let num_instances = instances.len() as u32;
-            if num_instances > 0
-            {
-                let vertices = self.create_vertices(blocktype);
-                let  vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Vertex Buffer"),
-                    contents: bytemuck::cast_slice(&vertices),
-                    usage: wgpu::BufferUsage::VERTEX,
-                });
-                let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Index Buffer"),
-                    contents: bytemuck::cast_slice(CUBE_INDICES),
-                    usage: wgpu::BufferUsage::INDEX,
-                });
-        
-                let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-                let instances_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some("Instance Buffer"),
-                    contents: bytemuck::cast_slice(&instance_data),
-                    usage: wgpu::BufferUsage::VERTEX,
-                });
-                
-                self.meshes.push(Mesh{
-                    blocktype: blocktype, 
-                    vertex_buffer: vertex_buffer,
-                    index_buffer: index_buffer,
-                    num_indexes: CUBE_INDICES.len() as u32,
-                    instances: instances,
-                    instances_buffer: instances_buffer,
-                    //uniform_bind_group_instances: uniform_bind_group_instances,
-                    num_instances: num_instances,
-                });
+        }
+        world
+    }
+
+    //Copy finished meshes to the GPU, nearest first, until budget bytes are used.
+    //At least one mesh is uploaded each frame, however big it is.
+    fn upload_meshes(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, center: ChunkPos, budget: usize) {
+        let mut ready: Vec<ChunkPos> = self.finished.keys().cloned().collect();
+        ready.sort_by_key(|pos| ChunkManager::distance2(center, *pos));
+        let mut used = 0;
+        for pos in ready {
+            let bytes = self.finished[&pos].size_in_bytes();
+            if used > 0 && used + bytes > budget {
+                break;
+            }
+            used += bytes;
+            let data = self.finished.remove(&pos).unwrap();
+            self.upload_mesh(device, queue, pos, data);
+        }
+    }
+
+    //Create, update or drop the GPU mesh of a chunk
+    fn upload_mesh(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, pos: ChunkPos, data: MeshData) {
+        if data.is_empty() {
+            self.meshes.remove(&pos);
+            return;
+        }
+        let chunk_size = self.world.chunk_size;
+        match self.meshes.get_mut(&pos) {
+            Some(mesh) => mesh.update(device, queue, chunk_size, &data),
+            None => {
+                self.meshes.insert(pos, Mesh::new(device, pos, chunk_size, &data));
             }
+        }
+    }
+
+    //Pick the meshes inside frustum for drawing. Call after update_chunks, before render.
+    pub fn cull(&mut self, frustum: &Frustum) {
+        self.visible = self.meshes_in(frustum);
+        self.cull_stats = CullStats {
+            drawn: self.visible.len(),
+            culled: self.meshes.len() - self.visible.len(),
         };
+    }
 
-        create_mesh_and_addto_model(BlockType::GRASS);
-        create_mesh_and_addto_model(BlockType::DIRT);
-        create_mesh_and_addto_model(BlockType::STONE);
-        
+    //Like cull, for the shadow map. frustum is the light's.
+    pub fn cull_shadows(&mut self, frustum: &Frustum) {
+        self.shadow_casters = self.meshes_in(frustum);
+    }
+
+    fn meshes_in(&self, frustum: &Frustum) -> Vec<ChunkPos> {
+        self.meshes
+            .iter()
+            .filter(|(_, mesh)| frustum.intersects(&mesh.bounds))
+            .map(|(pos, _)| *pos)
+            .collect()
+    }
+
+    //Write the chunks changed since they were loaded and the world settings. Does nothing for
+    //worlds without storage.
+    pub fn save(&mut self) -> Result<()> {
+        if let Some(storage) = &self.storage {
+            let world = &self.world;
+            let changed = world.modified.iter().filter_map(|pos| Some((*pos, world.chunks.get(pos)?)));
+            storage.save_chunks(changed)?;
+            storage.save_meta()?;
+            self.world.modified.clear();
+        }
+        Ok(())
     }
 }
 
@@ -483,113 +822,143 @@ pub trait DrawModel<'a, 'b>
 where
     'b: 'a,
 {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        material: &'b Material,
-        uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
-    );
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
         material: &'b Material,
         //instances: Range<u32>,
         uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
-    );
-
-    fn draw_model(
-        &mut self,
-        model: &'b Model,
-        uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
+        light: &'b wgpu::BindGroup,
     );
     fn draw_model_instanced(
         &mut self,
         model: &'b Model,
         //instances: Range<u32>,
         uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
-    );
-    fn draw_model_instanced_with_material(
-        &mut self,
-        model: &'b Model,
-        //material: &'b Material,
-        //instances: Range<u32>,
-        uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
+        light: &'b wgpu::BindGroup,
     );
+    //Depth only, with the shadow pipeline. light has the light's projection * view.
+    fn draw_shadow_casters(&mut self, model: &'b Model, light: &'b wgpu::BindGroup);
 }
 
 impl<'a, 'b> DrawModel<'a, 'b> for wgpu::RenderPass<'a>
 where
     'b: 'a,
 {
-    fn draw_mesh(
-        &mut self,
-        mesh: &'b Mesh,
-        material: &'b Material,
-        uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
-    ) {
-        self.draw_mesh_instanced(mesh, material, /*0..1,*/ uniforms/*, light*/);
-    }
-
     fn draw_mesh_instanced(
         &mut self,
         mesh: &'b Mesh,
         material: &'b Material,
         //instances: Range<u32>,
         uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
+        light: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
         self.set_vertex_buffer(1, mesh.instances_buffer.slice(..));
         self.set_index_buffer(mesh.index_buffer.slice(..));
         self.set_bind_group(0, &material.bind_group, &[]);
-        self.set_bind_group(1, &uniforms, &[]);
+        self.set_bind_group(1, uniforms, &[]);
 
-        //self.set_bind_group(2, &light, &[]);
+        self.set_bind_group(2, light, &[]);
         //self.draw_indexed(0..mesh.num_elements, 0, instances);
         self.draw_indexed(0..mesh.num_indexes, 0, 0..mesh.num_instances);        
     }
 
-    fn draw_model(
-        &mut self,
-        model: &'b Model,
-        uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
-    ) {
-        self.draw_model_instanced(model, /*0..1,*/ uniforms/*, light*/);
-    }
-
     fn draw_model_instanced(
         &mut self,
         model: &'b Model,
         //instances: Range<u32>,
         uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
+        light: &'b wgpu::BindGroup,
     ) {
         let material = model.material.as_ref().unwrap();
-        for mesh in &model.meshes {
+        for mesh in model.visible.iter().filter_map(|pos| model.meshes.get(pos)) {
             //let material = &model.materials[mesh.material];
             
-            self.draw_mesh_instanced(mesh, &material/*, instances.clone()*/, uniforms/*, light*/);
+            self.draw_mesh_instanced(mesh, material/*, instances.clone()*/, uniforms, light);
         }
     }
 
-    fn draw_model_instanced_with_material(
-        &mut self,
-        model: &'b Model,
-        //material: &'b Material,
-        //instances: Range<u32>,Copy, Clone
-        uniforms: &'b wgpu::BindGroup,
-        //light: &'b wgpu::BindGroup,
-    ) {
-        let material = model.material.as_ref().unwrap();
-        for mesh in &model.meshes {
-            self.draw_mesh_instanced(mesh, &material, /*instances.clone(),*/ uniforms/*, light*/);
+    fn draw_shadow_casters(&mut self, model: &'b Model, light: &'b wgpu::BindGroup) {
+        self.set_bind_group(0, light, &[]);
+        for mesh in model.shadow_casters.iter().filter_map(|pos| model.meshes.get(pos)) {
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_vertex_buffer(1, mesh.instances_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..));
+            self.draw_indexed(0..mesh.num_indexes, 0, 0..mesh.num_instances);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: i32 = 16;
+
+    //Empty chunks from -1 to 1 on each axis
+    fn world() -> World {
+        let mut world = World::new(SIZE, Arc::new(BlockRegistry::builtin().unwrap()));
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    world.chunks.insert(ChunkPos::new(x, y, z), Chunk::new(SIZE));
+                }
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn negative_blocks_round_down_to_their_chunk() {
+        let pos = BlockPos::new(-1, -16, -17);
+        assert_eq!(pos.chunk_pos(SIZE), ChunkPos::new(-1, -1, -2));
+        assert_eq!(pos.local(SIZE), [15, 0, 15]);
+        assert_eq!(BlockPos::new(15, 16, 0).chunk_pos(SIZE), ChunkPos::new(0, 1, 0));
+        assert_eq!(BlockPos::new(15, 16, 0).local(SIZE), [15, 0, 0]);
+        for pos in [BlockPos::new(-1, -16, -17), BlockPos::new(31, -33, 0)].iter() {
+            assert_eq!(pos.chunk_pos(SIZE).to_world(pos.local(SIZE), SIZE), *pos);
         }
     }
+
+    #[test]
+    fn interior_edit_marks_only_its_chunk() {
+        let mut world = world();
+        let stone = world.registry.id("stone").unwrap();
+        world.set_block(BlockPos::new(5, 6, 7), stone);
+        let origin = ChunkPos::new(0, 0, 0);
+        assert_eq!(world.dirty, [origin].iter().copied().collect());
+        assert_eq!(world.modified, [origin].iter().copied().collect());
+    }
+
+    #[test]
+    fn border_edit_marks_the_neighbours() {
+        let mut world = world();
+        let stone = world.registry.id("stone").unwrap();
+        world.set_block(BlockPos::new(0, 5, SIZE - 1), stone);
+        let origin = ChunkPos::new(0, 0, 0);
+        let expected: HashSet<ChunkPos> =
+            [origin, origin.offset(-1, 0, 0), origin.offset(0, 0, 1), origin.offset(-1, 0, 1)]
+                .iter()
+                .copied()
+                .collect();
+        let seeing: HashSet<ChunkPos> = world.chunks_seeing(BlockPos::new(0, 5, SIZE - 1)).into_iter().collect();
+        assert_eq!(seeing, expected);
+        assert_eq!(world.dirty, expected);
+        assert_eq!(world.modified, [origin].iter().copied().collect());
+
+        //A corner block is seen by all eight chunks around it
+        assert_eq!(world.chunks_seeing(BlockPos::new(-1, -1, -1)).len(), 8);
+    }
+
+    #[test]
+    fn remove_block_returns_the_old_block() {
+        let mut world = world();
+        let stone = world.registry.id("stone").unwrap();
+        let pos = BlockPos::new(-3, 4, 2);
+        assert_eq!(world.set_block(pos, stone), Some(BlockType::AIR));
+        assert_eq!(world.remove_block(pos), Some(stone));
+        assert_eq!(world.get_block(pos), Some(BlockType::AIR));
+        assert_eq!(world.remove_block(BlockPos::new(100, 0, 0)), None);
+    }
 }
@@ -0,0 +1,79 @@
+use std::mem;
+use wgpu::util::DeviceExt;
+
+use crate::model::BlockPos;
+
+//How far the outline sits outside the block, so it doesn't fight with the block's faces for depth
+const INFLATE: f32 = 0.002;
+
+//Corner i has x from bit 0, y from bit 1 and z from bit 2
+#[rustfmt::skip]
+const EDGES: [u32; 24] = [
+    0, 1, 2, 3, 4, 5, 6, 7, // along x
+    0, 2, 1, 3, 4, 6, 5, 7, // along y
+    0, 4, 1, 5, 2, 6, 3, 7, // along z
+];
+
+//Lines along the twelve edges of one block, drawn with PrimitiveTopology::LineList
+#[derive(Debug)]
+pub struct BlockOutline {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub num_indexes: u32,
+    //Nothing is drawn while this is None
+    pub block: Option<BlockPos>,
+}
+
+impl BlockOutline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Outline Vertex Buffer"),
+            size: mem::size_of::<[[f32; 3]; 8]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline Index Buffer"),
+            contents: bytemuck::cast_slice(&EDGES),
+            usage: wgpu::BufferUsage::INDEX,
+        });
+        Self {
+            vertex_buffer,
+            index_buffer,
+            num_indexes: EDGES.len() as u32,
+            block: None,
+        }
+    }
+
+    pub fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[wgpu::VertexAttributeDescriptor {
+                offset: 0,
+                shader_location: 0,
+                format: wgpu::VertexFormat::Float3,
+            }],
+        }
+    }
+
+    //Move the outline to block. The corners are in world space.
+    pub fn update(&mut self, queue: &wgpu::Queue, block: Option<BlockPos>) {
+        if block == self.block {
+            return;
+        }
+        self.block = block;
+        if let Some(pos) = block {
+            let mut corners = [[0.0f32; 3]; 8];
+            for (i, corner) in corners.iter_mut().enumerate() {
+                let offset = |bit: usize| if i & bit != 0 { 1.0 + INFLATE } else { -INFLATE };
+                *corner = [
+                    pos.x as f32 + offset(1),
+                    pos.y as f32 + offset(2),
+                    pos.z as f32 + offset(4),
+                ];
+            }
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&corners));
+        }
+    }
+}
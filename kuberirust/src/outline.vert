@@ -0,0 +1,15 @@
+#version 450
+
+layout(location=0) in vec3 a_position;
+
+// Same uniform buffer as the terrain, in its own pipeline layout
+layout(set=0, binding=0) 
+uniform Uniforms {
+    vec3 u_view_position; 
+    mat4 u_view_proj;
+};
+
+void main() {
+    // Outline corners are already in world space
+    gl_Position = u_view_proj * vec4(a_position, 1.0);
+}
\ No newline at end of file
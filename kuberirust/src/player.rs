@@ -0,0 +1,317 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use crate::model::{BlockPos, World};
+
+//Player size in blocks. The camera sits at eye height above the feet.
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+pub const EYE_HEIGHT: f32 = 1.62;
+
+//Speeds in blocks per second, gravity in blocks per second squared
+pub const GRAVITY: f32 = 28.0;
+pub const JUMP_SPEED: f32 = 9.0;
+pub const MAX_FALL_SPEED: f32 = 50.0;
+//Walking into a ledge at most this high climbs it. Blocks are whole, so this is one block.
+pub const STEP_HEIGHT: f32 = 1.0;
+//Longer frames are split up, so a hiccup doesn't throw the player through the floor
+const MAX_TIME_STEP: f32 = 0.05;
+//Slack for rounding errors when boxes touch
+const EPSILON: f32 = 1e-4;
+
+//Axis aligned box, min corner and max corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    //The unit cube of a block
+    pub fn block(pos: BlockPos) -> Self {
+        let min = Point3::new(pos.x as f32, pos.y as f32, pos.z as f32);
+        Self::new(min, min + Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    //The space the player takes up with the camera at eye
+    pub fn player(eye: Point3<f32>) -> Self {
+        let half = PLAYER_WIDTH / 2.0;
+        let feet = eye.y - EYE_HEIGHT;
+        Self::new(
+            Point3::new(eye.x - half, feet, eye.z - half),
+            Point3::new(eye.x + half, feet + PLAYER_HEIGHT, eye.z + half),
+        )
+    }
+
+    pub fn translate(&self, offset: Vector3<f32>) -> Self {
+        Self::new(self.min + offset, self.max + offset)
+    }
+
+    //Overlapping volume, touching faces don't count
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x < other.max.x
+            && self.max.x > other.min.x
+            && self.min.y < other.max.y
+            && self.max.y > other.min.y
+            && self.min.z < other.max.z
+            && self.max.z > other.min.z
+    }
+}
+
+//A walking player, colliding with solid blocks. Chunks that aren't loaded count as solid,
+//so the player waits instead of falling out of the world while they load.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Player {
+    //Middle of the feet
+    pub position: Point3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+}
+
+impl Player {
+    pub fn new(position: Point3<f32>) -> Self {
+        Self {
+            position,
+            velocity: Vector3::zero(),
+            on_ground: false,
+        }
+    }
+
+    //Player with the camera at eye
+    pub fn at_eye(eye: Point3<f32>) -> Self {
+        Self::new(eye - Vector3::unit_y() * EYE_HEIGHT)
+    }
+
+    pub fn eye(&self) -> Point3<f32> {
+        self.position + Vector3::unit_y() * EYE_HEIGHT
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::player(self.eye())
+    }
+
+    //movement is the wanted horizontal velocity, its y is ignored
+    pub fn update(&mut self, world: &World, movement: Vector3<f32>, jump: bool, dt: f32) {
+        let mut left = dt;
+        while left > 0.0 {
+            let step = left.min(MAX_TIME_STEP);
+            self.walk(world, movement, jump, step);
+            left -= step;
+        }
+    }
+
+    fn walk(&mut self, world: &World, movement: Vector3<f32>, jump: bool, dt: f32) {
+        self.velocity.x = movement.x;
+        self.velocity.z = movement.z;
+        if jump && self.on_ground {
+            self.velocity.y = JUMP_SPEED;
+        }
+        self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-MAX_FALL_SPEED);
+
+        //Vertical first, so on_ground is known when walking
+        let dy = self.velocity.y * dt;
+        let moved_y = sweep(world, &self.aabb(), 1, dy);
+        self.position.y += moved_y;
+        if moved_y != dy {
+            self.on_ground = dy < 0.0;
+            self.velocity.y = 0.0;
+        } else {
+            self.on_ground = false;
+        }
+
+        let offset = self.move_horizontal(world, self.velocity.x * dt, self.velocity.z * dt);
+        self.position += offset;
+    }
+
+    //Slide along walls. On the ground, also try to climb onto whatever is in the way.
+    fn move_horizontal(&self, world: &World, dx: f32, dz: f32) -> Vector3<f32> {
+        let aabb = self.aabb();
+        let (x, z) = slide(world, &aabb, dx, dz);
+        if !self.on_ground || (x == dx && z == dz) {
+            return Vector3::new(x, 0.0, z);
+        }
+
+        let up = sweep(world, &aabb, 1, STEP_HEIGHT);
+        let raised = aabb.translate(Vector3::new(0.0, up, 0.0));
+        let (step_x, step_z) = slide(world, &raised, dx, dz);
+        let down = sweep(world, &raised.translate(Vector3::new(step_x, 0.0, step_z)), 1, -up);
+        if step_x * step_x + step_z * step_z > x * x + z * z + EPSILON {
+            Vector3::new(step_x, up + down, step_z)
+        } else {
+            Vector3::new(x, 0.0, z)
+        }
+    }
+}
+
+//x first, then z from where x ended
+fn slide(world: &World, aabb: &Aabb, dx: f32, dz: f32) -> (f32, f32) {
+    let x = sweep(world, aabb, 0, dx);
+    let z = sweep(world, &aabb.translate(Vector3::new(x, 0.0, 0.0)), 2, dz);
+    (x, z)
+}
+
+fn is_solid(world: &World, pos: BlockPos) -> bool {
+    world
+        .get_block(pos)
+        .map_or(true, |blocktype| world.registry.is_solid(blocktype))
+}
+
+//How far aabb can move along axis (0 = x, 1 = y, 2 = z), up to amount, before touching a solid block.
+//Blocks the box already overlaps are ignored, so a stuck player can always get out.
+pub fn sweep(world: &World, aabb: &Aabb, axis: usize, amount: f32) -> f32 {
+    if amount == 0.0 {
+        return 0.0;
+    }
+    //Blocks the box passes through on the way
+    let mut lo = [0; 3];
+    let mut hi = [0; 3];
+    for a in 0..3 {
+        let (mut min, mut max) = (aabb.min[a], aabb.max[a]);
+        if a == axis {
+            if amount > 0.0 {
+                max += amount;
+            } else {
+                min += amount;
+            }
+        }
+        lo[a] = min.floor() as i32;
+        hi[a] = (max - EPSILON).floor() as i32;
+    }
+
+    let mut allowed = amount;
+    for x in lo[0]..=hi[0] {
+        for y in lo[1]..=hi[1] {
+            for z in lo[2]..=hi[2] {
+                let pos = BlockPos::new(x, y, z);
+                if !is_solid(world, pos) {
+                    continue;
+                }
+                let block = Aabb::block(pos);
+                let overlaps_sideways = (0..3).filter(|a| *a != axis).all(|a| {
+                    aabb.min[a] < block.max[a] - EPSILON && aabb.max[a] > block.min[a] + EPSILON
+                });
+                if !overlaps_sideways {
+                    continue;
+                }
+                if amount > 0.0 && block.min[axis] >= aabb.max[axis] - EPSILON {
+                    allowed = allowed.min((block.min[axis] - aabb.max[axis]).max(0.0));
+                } else if amount < 0.0 && block.max[axis] <= aabb.min[axis] + EPSILON {
+                    allowed = allowed.max((block.max[axis] - aabb.min[axis]).min(0.0));
+                }
+            }
+        }
+    }
+    allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockRegistry, Chunk, ChunkPos};
+    use std::sync::Arc;
+
+    const SIZE: i32 = 16;
+    const DT: f32 = 1.0 / 60.0;
+
+    //Empty chunks around the origin with a stone floor at y = 0, so standing on it the feet are at 1
+    fn world() -> World {
+        let registry = Arc::new(BlockRegistry::builtin().unwrap());
+        let mut world = World::new(SIZE, registry);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    world.chunks.insert(ChunkPos::new(x, y, z), Chunk::new(SIZE));
+                }
+            }
+        }
+        for x in -10..10 {
+            for z in -10..10 {
+                place(&mut world, BlockPos::new(x, 0, z));
+            }
+        }
+        world
+    }
+
+    fn place(world: &mut World, pos: BlockPos) {
+        let stone = world.registry.id("stone").unwrap();
+        world.set_block(pos, stone);
+    }
+
+    fn run(player: &mut Player, world: &World, movement: Vector3<f32>, jump: bool, seconds: f32) {
+        for _ in 0..(seconds / DT) as usize {
+            player.update(world, movement, jump, DT);
+        }
+    }
+
+    fn standing(world: &World) -> Player {
+        let mut player = Player::new(Point3::new(0.5, 1.0, 0.5));
+        run(&mut player, world, Vector3::zero(), false, 0.1);
+        assert!(player.on_ground);
+        player
+    }
+
+    #[test]
+    fn falls_onto_the_floor() {
+        let world = world();
+        let mut player = Player::new(Point3::new(0.5, 10.0, 0.5));
+        run(&mut player, &world, Vector3::zero(), false, 2.0);
+        assert!((player.position.y - 1.0).abs() < 1e-3, "{:?}", player.position);
+        assert!(player.on_ground);
+        assert_eq!(player.velocity.y, 0.0);
+
+        //One long frame doesn't fall through
+        let mut player = Player::new(Point3::new(0.5, 12.0, 0.5));
+        player.velocity.y = -MAX_FALL_SPEED;
+        player.update(&world, Vector3::zero(), false, 3.0);
+        assert!((player.position.y - 1.0).abs() < 1e-3, "{:?}", player.position);
+    }
+
+    #[test]
+    fn jumps_and_comes_back_down() {
+        let world = world();
+        let mut player = standing(&world);
+        let mut highest = player.position.y;
+        player.update(&world, Vector3::zero(), true, DT);
+        assert!(!player.on_ground);
+        for _ in 0..120 {
+            player.update(&world, Vector3::zero(), false, DT);
+            highest = highest.max(player.position.y);
+        }
+        //v² / 2g is about 1.45 blocks
+        assert!(highest > 2.3 && highest < 2.6, "highest {}", highest);
+        assert!((player.position.y - 1.0).abs() < 1e-3, "{:?}", player.position);
+        assert!(player.on_ground);
+    }
+
+    #[test]
+    fn walks_up_one_block() {
+        let mut world = world();
+        for x in 3..10 {
+            for z in -10..10 {
+                place(&mut world, BlockPos::new(x, 1, z));
+            }
+        }
+        let mut player = standing(&world);
+        run(&mut player, &world, Vector3::new(4.0, 0.0, 0.0), false, 1.0);
+        assert!(player.position.x > 3.5, "{:?}", player.position);
+        assert!((player.position.y - 2.0).abs() < 1e-3, "{:?}", player.position);
+    }
+
+    #[test]
+    fn stopped_by_a_two_block_wall() {
+        let mut world = world();
+        for y in 1..=2 {
+            for z in -10..10 {
+                place(&mut world, BlockPos::new(3, y, z));
+            }
+        }
+        let mut player = standing(&world);
+        run(&mut player, &world, Vector3::new(4.0, 0.0, 0.0), false, 1.0);
+        assert!((player.position.x - (3.0 - PLAYER_WIDTH / 2.0)).abs() < 1e-3, "{:?}", player.position);
+        assert!((player.position.y - 1.0).abs() < 1e-3, "{:?}", player.position);
+    }
+}
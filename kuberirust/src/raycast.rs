@@ -0,0 +1,189 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use crate::model::{BlockPos, BlockType, World};
+
+//Rays stop after this many blocks, whatever max_distance they are given, so a huge or NaN one
+//can't keep them going forever through unloaded chunks
+const MAX_RAYCAST_DISTANCE: f32 = 1024.0;
+
+//The block a ray stopped at
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub block: BlockPos,
+    pub blocktype: BlockType,
+    //Normal of the face the ray entered through, e.g. [0, 1, 0] when coming from above.
+    //All zero if the ray started inside the block.
+    pub normal: [i32; 3],
+    //Along the ray, from origin to where it enters the block
+    pub distance: f32,
+}
+
+impl RaycastHit {
+    //The empty block in front of the hit face, where a new block would go
+    pub fn adjacent(&self) -> BlockPos {
+        self.block.offset(self.normal[0], self.normal[1], self.normal[2])
+    }
+}
+
+impl World {
+    //First solid block along the ray, visiting every block the ray passes through (Amanatides & Woo).
+    //Chunks that aren't loaded count as air. direction doesn't need to be normalized.
+    pub fn raycast(&self, origin: Point3<f32>, direction: Vector3<f32>, max_distance: f32) -> Option<RaycastHit> {
+        let length = direction.magnitude();
+        if length == 0.0 || !length.is_finite() {
+            return None;
+        }
+        let dir = direction / length;
+        let max_distance = max_distance.min(MAX_RAYCAST_DISTANCE);
+        let origin = [origin.x, origin.y, origin.z];
+        let dir = [dir.x, dir.y, dir.z];
+
+        let start = BlockPos::containing(Point3::new(origin[0], origin[1], origin[2]));
+        let mut block = [start.x, start.y, start.z];
+        let mut step = [0; 3];
+        //Distance along the ray to the next block border on each axis, and between two borders
+        let mut t_max = [f32::INFINITY; 3];
+        let mut t_delta = [f32::INFINITY; 3];
+        for a in 0..3 {
+            if dir[a] > 0.0 {
+                step[a] = 1;
+                t_delta[a] = 1.0 / dir[a];
+                t_max[a] = ((block[a] + 1) as f32 - origin[a]) / dir[a];
+            } else if dir[a] < 0.0 {
+                step[a] = -1;
+                t_delta[a] = -1.0 / dir[a];
+                t_max[a] = (block[a] as f32 - origin[a]) / dir[a];
+            }
+        }
+
+        let mut normal = [0; 3];
+        let mut distance = 0.0;
+        loop {
+            let pos = BlockPos::new(block[0], block[1], block[2]);
+            if let Some(blocktype) = self.get_block(pos) {
+                if self.registry.is_solid(blocktype) {
+                    return Some(RaycastHit {
+                        block: pos,
+                        blocktype,
+                        normal,
+                        distance,
+                    });
+                }
+            }
+
+            let a = if t_max[0] < t_max[1] {
+                if t_max[0] < t_max[2] { 0 } else { 2 }
+            } else if t_max[1] < t_max[2] {
+                1
+            } else {
+                2
+            };
+            distance = t_max[a];
+            if distance > max_distance {
+                return None;
+            }
+            block[a] += step[a];
+            t_max[a] += t_delta[a];
+            normal = [0; 3];
+            normal[a] = -step[a];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{BlockRegistry, Chunk, ChunkPos};
+    use std::sync::Arc;
+
+    const SIZE: i32 = 16;
+
+    //Empty chunks from -SIZE to SIZE on every axis, with stone at the given blocks
+    fn world(stone: &[BlockPos]) -> World {
+        let registry = Arc::new(BlockRegistry::builtin().unwrap());
+        let mut world = World::new(SIZE, registry.clone());
+        for x in -1..=0 {
+            for y in -1..=0 {
+                for z in -1..=0 {
+                    world.chunks.insert(ChunkPos::new(x, y, z), Chunk::new(SIZE));
+                }
+            }
+        }
+        let blocktype = registry.id("stone").unwrap();
+        for pos in stone {
+            world.set_block(*pos, blocktype);
+        }
+        world
+    }
+
+    fn center(pos: BlockPos) -> Point3<f32> {
+        Point3::new(pos.x as f32 + 0.5, pos.y as f32 + 0.5, pos.z as f32 + 0.5)
+    }
+
+    fn assert_hit(hit: Option<RaycastHit>, block: BlockPos, normal: [i32; 3], distance: f32) {
+        let hit = hit.expect("expected a hit");
+        assert_eq!(hit.block, block);
+        assert_eq!(hit.normal, normal);
+        assert!((hit.distance - distance).abs() < 1e-4, "distance {} != {}", hit.distance, distance);
+    }
+
+    #[test]
+    fn axis_aligned_rays_hit_the_facing_side() {
+        let target = BlockPos::new(-8, -8, -8);
+        let world = world(&[target]);
+        for a in 0..3 {
+            for &sign in &[-1.0, 1.0] {
+                let mut direction = Vector3::zero();
+                direction[a] = sign;
+                //Start 3.5 blocks away from the center of the block, looking at it
+                let origin = center(target) - direction * 3.5;
+                let mut normal = [0; 3];
+                normal[a] = -sign as i32;
+                assert_hit(world.raycast(origin, direction, 10.0), target, normal, 3.0);
+            }
+        }
+    }
+
+    #[test]
+    fn misses_beyond_max_distance() {
+        let world = world(&[BlockPos::new(-8, -8, -4)]);
+        let origin = Point3::new(-7.5, -7.5, -7.5);
+        let direction = Vector3::unit_z();
+        assert!(world.raycast(origin, direction, 3.4).is_none());
+        assert!(world.raycast(origin, direction, 3.6).is_some());
+        assert!(world.raycast(origin, -direction, 10.0).is_none());
+        assert!(world.raycast(origin, -direction, f32::INFINITY).is_none());
+        assert!(world.raycast(origin, -direction, f32::NAN).is_none());
+    }
+
+    #[test]
+    fn adjacent_is_in_front_of_the_hit_face() {
+        let target = BlockPos::new(-8, -8, -8);
+        let world = world(&[target]);
+        let hit = world.raycast(Point3::new(-7.5, -3.0, -7.5), -Vector3::unit_y(), 10.0).unwrap();
+        assert_eq!(hit.normal, [0, 1, 0]);
+        assert_eq!(hit.adjacent(), BlockPos::new(-8, -7, -8));
+        //Starting inside a block there is no face
+        let hit = world.raycast(center(target), Vector3::unit_x(), 10.0).unwrap();
+        assert_eq!(hit.normal, [0, 0, 0]);
+        assert_eq!(hit.distance, 0.0);
+        assert_eq!(hit.adjacent(), target);
+    }
+
+    #[test]
+    fn hits_across_chunk_borders_and_negative_coordinates() {
+        //Each ray crosses from the chunk at the origin into one at negative coordinates
+        let world = world(&[BlockPos::new(-3, 2, 2), BlockPos::new(2, -1, 2), BlockPos::new(2, 2, -16)]);
+        let origin = Point3::new(2.5, 2.5, 2.5);
+        assert_hit(world.raycast(origin, -Vector3::unit_x(), 10.0), BlockPos::new(-3, 2, 2), [1, 0, 0], 4.5);
+        assert_hit(world.raycast(origin, -Vector3::unit_y(), 10.0), BlockPos::new(2, -1, 2), [0, 1, 0], 2.5);
+        assert_hit(world.raycast(origin, -Vector3::unit_z(), 20.0), BlockPos::new(2, 2, -16), [0, 0, 1], 17.5);
+        //Diagonally, entering the block through one of its faces
+        let target = BlockPos::new(-2, -2, -2);
+        let hit = self::world(&[target]).raycast(Point3::new(1.3, 1.6, 1.9), Vector3::new(-1.0, -1.0, -1.0), 10.0).unwrap();
+        assert_eq!(hit.block, target);
+        assert_eq!(hit.normal.iter().map(|c| c.abs()).sum::<i32>(), 1);
+        assert_eq!(hit.adjacent().chunk_pos(SIZE), ChunkPos::new(-1, -1, -1));
+    }
+}
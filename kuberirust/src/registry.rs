@@ -0,0 +1,263 @@
+use anyhow::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::mesher::{Face, FACES};
+
+//Block definitions in a world directory, used instead of the built-in ones
+pub const BLOCKS_FILE: &str = "blocks.ron";
+
+//Id of a block kind. Index into BlockRegistry, 0 is always air.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Default, PartialOrd, Ord)]
+pub struct BlockType(pub u16);
+
+impl BlockType {
+    pub const AIR: BlockType = BlockType(0);
+}
+
+
+//How one block kind looks and behaves
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockDef {
+    pub name: String,
+    //Indexed by Face as ordered in FACES
+    //Tile names in the texture atlas (file names in src/tiles without .png)
+    pub tiles: [String; 6],
+    //Collides and hides the faces of its neighbours
+    pub solid: bool,
+    //Neighbours are visible through it, and light passes
+    pub transparent: bool,
+    //Light emitted, 0-15
+    pub light: u8,
+}
+
+impl BlockDef {
+    pub fn tile(&self, face: Face) -> &str {
+        &self.tiles[FACES.iter().position(|f| *f == face).unwrap()]
+    }
+
+    //Hides neighbouring faces
+    pub fn is_opaque(&self) -> bool {
+        self.solid && !self.transparent
+    }
+}
+
+//File format, see blocks.ron
+#[derive(Deserialize)]
+struct RegistryFile {
+    blocks: Vec<BlockFile>,
+}
+
+#[derive(Deserialize)]
+struct BlockFile {
+    name: String,
+    faces: FacesFile,
+    #[serde(default = "default_solid")]
+    solid: bool,
+    #[serde(default)]
+    transparent: bool,
+    #[serde(default)]
+    light: u8,
+}
+
+fn default_solid() -> bool {
+    true
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FacesFile {
+    all: Option<String>,
+    side: Option<String>,
+    top: Option<String>,
+    bottom: Option<String>,
+    right: Option<String>,
+    left: Option<String>,
+    front: Option<String>,
+    back: Option<String>,
+}
+
+impl FacesFile {
+    //Most specific entry wins: single face, then top/bottom/side, then all
+    fn resolve(&self, face: Face) -> Option<&String> {
+        let (single, group) = match face {
+            Face::Top => (None, &self.top),
+            Face::Bottom => (None, &self.bottom),
+            Face::Right => (self.right.as_ref(), &self.side),
+            Face::Left => (self.left.as_ref(), &self.side),
+            Face::Front => (self.front.as_ref(), &self.side),
+            Face::Back => (self.back.as_ref(), &self.side),
+        };
+        single.or(group.as_ref()).or(self.all.as_ref())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlockRegistry {
+    blocks: Vec<BlockDef>,
+    by_name: HashMap<String, BlockType>,
+}
+
+impl BlockRegistry {
+    pub const MAX_LIGHT: u8 = 15;
+
+    //The blocks in blocks.ron, compiled into the binary
+    pub fn builtin() -> Result<Self> {
+        Self::from_ron(include_str!("blocks.ron")).context("Invalid built-in blocks.ron")
+    }
+
+    //blocks.ron in a world directory if there is one, otherwise the built-in blocks.
+    //Blocks can then be changed without recompiling, as long as their tiles are in the atlas.
+    pub fn for_world<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let path = dir.as_ref().join(BLOCKS_FILE);
+        if path.exists() {
+            Self::load(path)
+        } else {
+            Self::builtin()
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let src = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read block registry {}", path.display()))?;
+        Self::from_ron(&src).with_context(|| format!("Invalid block registry {}", path.display()))
+    }
+
+    pub fn from_ron(src: &str) -> Result<Self> {
+        //Start the file with #![enable(implicit_some)] to write face tiles without Some(...)
+        let file: RegistryFile = ron::de::from_str(src)?;
+
+        let air = BlockDef {
+            name: String::from("air"),
+            tiles: Default::default(),
+            solid: false,
+            transparent: true,
+            light: 0,
+        };
+        let mut registry = Self {
+            blocks: vec![air],
+            by_name: HashMap::new(),
+        };
+        registry.by_name.insert(String::from("air"), BlockType::AIR);
+
+        for block in file.blocks {
+            if registry.by_name.contains_key(&block.name) {
+                bail!("Block '{}' is defined twice", block.name);
+            }
+            if block.light > Self::MAX_LIGHT {
+                bail!("Block '{}' has light {}, max is {}", block.name, block.light, Self::MAX_LIGHT);
+            }
+            let mut tiles: [String; 6] = Default::default();
+            for (tile, face) in tiles.iter_mut().zip(FACES.iter()) {
+                *tile = block
+                    .faces
+                    .resolve(*face)
+                    .with_context(|| format!("Block '{}' has no tile for face {:?}", block.name, face))?
+                    .clone();
+            }
+            if registry.blocks.len() > u16::MAX as usize {
+                bail!("Too many blocks");
+            }
+            let id = BlockType(registry.blocks.len() as u16);
+            registry.by_name.insert(block.name.clone(), id);
+            registry.blocks.push(BlockDef {
+                name: block.name,
+                tiles,
+                solid: block.solid,
+                transparent: block.transparent,
+                light: block.light,
+            });
+        }
+        Ok(registry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    //Unknown ids are treated as air
+    pub fn get(&self, blocktype: BlockType) -> &BlockDef {
+        self.blocks.get(blocktype.0 as usize).unwrap_or(&self.blocks[0])
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<BlockType> {
+        self.by_name.get(name).copied()
+    }
+
+    //Like by_name, but missing blocks are an error
+    pub fn id(&self, name: &str) -> Result<BlockType> {
+        self.by_name(name)
+            .with_context(|| format!("Block '{}' not found in registry", name))
+    }
+
+    //All block kinds except air, with their ids
+    pub fn iter(&self) -> impl Iterator<Item = (BlockType, &BlockDef)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, def)| (BlockType(i as u16), def))
+    }
+
+    pub fn is_solid(&self, blocktype: BlockType) -> bool {
+        self.get(blocktype).solid
+    }
+
+    pub fn is_opaque(&self, blocktype: BlockType) -> bool {
+        self.get(blocktype).is_opaque()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(src: &str) -> String {
+        BlockRegistry::from_ron(src).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn builtin_blocks_load() {
+        let registry = BlockRegistry::builtin().unwrap();
+        assert_eq!(registry.by_name("air"), Some(BlockType::AIR));
+        assert_eq!(registry.id("grass").unwrap(), BlockType(1));
+        assert_eq!(registry.get(registry.id("lamp").unwrap()).light, 14);
+        assert!(registry.id("glass").is_err());
+    }
+
+    #[test]
+    fn most_specific_face_tile_wins() {
+        let registry = BlockRegistry::from_ron(
+            r#"(blocks: [(name: "log", faces: (all: Some("bark"), side: Some("side"), front: Some("cut")))])"#,
+        )
+        .unwrap();
+        let log = registry.get(registry.id("log").unwrap());
+        assert_eq!(log.tile(Face::Front), "cut");
+        assert_eq!(log.tile(Face::Back), "side");
+        assert_eq!(log.tile(Face::Left), "side");
+        assert_eq!(log.tile(Face::Top), "bark");
+        assert_eq!(log.tile(Face::Bottom), "bark");
+    }
+
+    #[test]
+    fn duplicate_names_are_an_error() {
+        let message = error(
+            r#"(blocks: [(name: "stone", faces: (all: Some("stone"))), (name: "stone", faces: (all: Some("dirt")))])"#,
+        );
+        assert!(message.contains("defined twice"), "{}", message);
+    }
+
+    #[test]
+    fn too_much_light_is_an_error() {
+        let message = error(r#"(blocks: [(name: "sun", faces: (all: Some("lamp")), light: 16)])"#);
+        assert!(message.contains("max is 15"), "{}", message);
+    }
+
+    #[test]
+    fn missing_face_tile_is_an_error() {
+        let message = error(r#"(blocks: [(name: "log", faces: (top: Some("bark"), side: Some("side")))])"#);
+        assert!(message.contains("no tile for face Bottom"), "{}", message);
+    }
+}
@@ -1,12 +1,96 @@
 #version 450
 
 layout(location=0) in vec2 v_tex_coords;
+layout(location=1) in vec4 v_tile;
+layout(location=2) in vec3 v_normal;
+layout(location=3) in float v_ao;
+// Sky light and block light, 0 to 1
+layout(location=4) in vec2 v_light;
+// World space
+layout(location=5) in vec3 v_position;
 
 layout(location=0) out vec4 f_color;
 
 layout(set = 0, binding = 0) uniform texture2D t_diffuse;
 layout(set = 0, binding = 1) uniform sampler s_diffuse;
 
+layout(set = 1, binding = 0)
+uniform Uniforms {
+    vec3 u_view_position;
+    mat4 u_view_proj;
+};
+
+layout(set = 2, binding = 0)
+uniform Light {
+    vec3 u_light_direction;
+    // How bright sky light is at this time of day, 0 to 1
+    float u_daylight;
+    vec3 u_light_color;
+    float u_ambient;
+    // World space to the shadow map
+    mat4 u_light_view_proj;
+};
+
+// u_fog_mode is 0 for no fog, 1 for linear and 2 for exponential
+layout(set = 2, binding = 1)
+uniform Fog {
+    vec3 u_fog_color;
+    float u_fog_density;
+    float u_fog_start;
+    float u_fog_end;
+    uint u_fog_mode;
+};
+
+// Depth seen from the light
+layout(set = 2, binding = 2) uniform texture2D t_shadow;
+layout(set = 2, binding = 3) uniform samplerShadow s_shadow;
+
+// 1 where the light reaches position, 0 in shadow. Averages 3x3 texels to soften the edges.
+float shadow(vec3 position, vec3 normal) {
+    // A little off the surface, so it doesn't shadow itself
+    vec4 light_position = u_light_view_proj * vec4(position + normal * 0.05, 1.0);
+    vec3 p = light_position.xyz / light_position.w;
+    if (abs(p.x) > 1.0 || abs(p.y) > 1.0 || p.z > 1.0) {
+        return 1.0;
+    }
+    vec2 uv = p.xy * vec2(0.5, -0.5) + 0.5;
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DShadow(t_shadow, s_shadow), 0));
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            lit += texture(sampler2DShadow(t_shadow, s_shadow), vec3(uv + vec2(x, y) * texel, p.z));
+        }
+    }
+    lit /= 9.0;
+    // Fade out towards the edge of the shadow map instead of stopping sharply
+    return mix(lit, 1.0, smoothstep(0.8, 1.0, max(abs(p.x), abs(p.y))));
+}
+
 void main() {
-    f_color = texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords);
+    // Repeat the tile across merged quads
+    vec2 uv = v_tile.xy + fract(v_tex_coords) * v_tile.zw;
+    vec4 color = texture(sampler2D(t_diffuse, s_diffuse), uv);
+
+    // Each light level is 80% of the one above it
+    vec2 level = pow(vec2(0.8), 15.0 * (1.0 - v_light));
+
+    // Ambient everywhere, plus diffuse for faces turned towards the light and not in shadow,
+    // as far as the sky reaches
+    vec3 normal = normalize(v_normal);
+    float diffuse = max(dot(normal, u_light_direction), 0.0) * shadow(v_position, normal);
+    vec3 sun = u_light_color * (u_ambient + (1.0 - u_ambient) * diffuse) * level.x * u_daylight;
+    // Warm light from light blocks, and a little so caves aren't pitch black
+    vec3 lamp = vec3(1.0, 0.85, 0.6) * level.y;
+    vec3 light = max(max(sun, lamp), vec3(0.02));
+    // Corners between blocks get less light, down to 40% in the darkest ones
+    light *= mix(0.4, 1.0, v_ao);
+
+    float distance = length(v_position - u_view_position);
+    float fog = 0.0;
+    if (u_fog_mode == 1u) {
+        fog = clamp((distance - u_fog_start) / (u_fog_end - u_fog_start), 0.0, 1.0);
+    } else if (u_fog_mode == 2u) {
+        fog = 1.0 - exp(-u_fog_density * distance);
+    }
+    f_color = vec4(mix(color.rgb * light, u_fog_color, fog), color.a);
 }
\ No newline at end of file
@@ -2,8 +2,17 @@
 
 layout(location=0) in vec3 a_position;
 layout(location=1) in vec2 a_tex_coords;
+layout(location=2) in vec3 a_normal;
+layout(location=9) in vec4 a_tile;
+layout(location=10) in float a_ao;
+layout(location=11) in vec2 a_light;
 
 layout(location=0) out vec2 v_tex_coords;
+layout(location=1) out vec4 v_tile;
+layout(location=2) out vec3 v_normal;
+layout(location=3) out float v_ao;
+layout(location=4) out vec2 v_light;
+layout(location=5) out vec3 v_position;
 
 layout(set=1, binding=0) 
 uniform Uniforms {
@@ -16,5 +25,12 @@ layout(location=5) in mat4 model_matrix;
 
 void main() {
     v_tex_coords = a_tex_coords;    // UPDATED!
-    gl_Position = u_view_proj * model_matrix * vec4(a_position, 1.0);
+    v_tile = a_tile;
+    v_ao = a_ao;
+    v_light = a_light;
+    // Chunks are only moved, never rotated or scaled, so the normal stays as it is
+    v_normal = mat3(model_matrix) * a_normal;
+    vec4 world_position = model_matrix * vec4(a_position, 1.0);
+    v_position = world_position.xyz;
+    gl_Position = u_view_proj * world_position;
 }
\ No newline at end of file
@@ -0,0 +1,125 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector3};
+
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::model::{InstanceRaw, ModelVertex, Vertex};
+use crate::texture::Texture;
+
+//Texels along each side of the shadow map
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+//Shadows reach this far from the camera, in blocks. Further away there are none.
+const SHADOW_RADIUS: f32 = 48.0;
+//Blocks this far towards and away from the light, from the camera, cast shadows
+const SHADOW_DEPTH: f32 = 128.0;
+
+//Orthographic projection * view from the light, centred on center. Moves with the camera in whole
+//texels, so the edges of shadows don't crawl when it moves.
+pub fn light_view_proj(direction: Vector3<f32>, center: Point3<f32>) -> Matrix4<f32> {
+    //Any up will do, as long as it isn't along the light
+    let up = if direction.x.abs() < 1e-3 && direction.z.abs() < 1e-3 {
+        Vector3::unit_z()
+    } else {
+        Vector3::unit_y()
+    };
+    let view = Matrix4::look_at_dir(Point3::origin(), -direction, up);
+    let center = view.transform_point(center);
+    let texel = 2.0 * SHADOW_RADIUS / SHADOW_MAP_SIZE as f32;
+    let x = (center.x / texel).floor() * texel;
+    let y = (center.y / texel).floor() * texel;
+    //The view looks down -z
+    let projection = cgmath::ortho(
+        x - SHADOW_RADIUS,
+        x + SHADOW_RADIUS,
+        y - SHADOW_RADIUS,
+        y + SHADOW_RADIUS,
+        -center.z - SHADOW_DEPTH,
+        -center.z + SHADOW_DEPTH,
+    );
+    OPENGL_TO_WGPU_MATRIX * projection * view
+}
+
+//Depth of the terrain as seen from the sun or moon. shader.frag compares against it to find out
+//what is in shadow.
+pub struct ShadowMap {
+    pub texture: Texture,
+    //Depth only, no fragment shader
+    pub pipeline: wgpu::RenderPipeline,
+    //The light uniform, for the shadow pass
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    //light_buffer holds the Light uniform with the light's projection * view
+    pub fn new(device: &wgpu::Device, light_buffer: &wgpu::Buffer) -> Self {
+        let texture =
+            Texture::create_depth_texture_with_size(device, SHADOW_MAP_SIZE, SHADOW_MAP_SIZE, "shadow_texture");
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("shadow_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(light_buffer.slice(..)),
+            }],
+            label: Some("shadow_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let vs_module = device.create_shader_module(wgpu::include_spirv!("shadow.vert.spv"));
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Shadow Pipeline"),
+            layout: Some(&layout),
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &vs_module,
+                entry_point: "main",
+            },
+            fragment_stage: None,
+            //Both sides, since the faces turned away from the light are often missing between
+            //blocks. The bias keeps lit faces from shadowing themselves.
+            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: wgpu::CullMode::None,
+                depth_bias: 2,
+                depth_bias_slope_scale: 2.0,
+                depth_bias_clamp: 0.0,
+                clamp_depth: false,
+            }),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            color_states: &[],
+            depth_stencil_state: Some(wgpu::DepthStencilStateDescriptor {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilStateDescriptor::default(),
+            }),
+            sample_count: 1,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: wgpu::IndexFormat::Uint32,
+                vertex_buffers: &[ModelVertex::desc(), InstanceRaw::desc()],
+            },
+        });
+
+        Self {
+            texture,
+            pipeline,
+            bind_group,
+        }
+    }
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location=0) in vec3 a_position;
+layout(location=5) in mat4 model_matrix;
+
+// Same buffer as the Light block in shader.frag
+layout(set=0, binding=0)
+uniform Light {
+    vec3 u_light_direction;
+    float u_daylight;
+    vec3 u_light_color;
+    float u_ambient;
+    mat4 u_light_view_proj;
+};
+
+void main() {
+    gl_Position = u_light_view_proj * model_matrix * vec4(a_position, 1.0);
+}
@@ -0,0 +1,53 @@
+#version 450
+
+layout(location=0) in vec3 v_direction;
+
+layout(location=0) out vec4 f_color;
+
+layout(set=1, binding=0)
+uniform Sky {
+    vec3 u_sun_direction;
+    float u_stars;
+    vec3 u_zenith_color;
+    // Angular radius, radians
+    float u_sun_size;
+    vec3 u_horizon_color;
+};
+
+// Pseudo random, 0 to 1
+float hash(vec3 p) {
+    p = fract(p * vec3(0.1031, 0.1030, 0.0973));
+    p += dot(p, p.yxz + 33.33);
+    return fract((p.x + p.y) * p.z);
+}
+
+// 1 inside a disc of radius size around center, fading out at the edge
+float disc(vec3 direction, vec3 center, float size) {
+    float angle = acos(clamp(dot(direction, center), -1.0, 1.0));
+    return 1.0 - smoothstep(size * 0.8, size, angle);
+}
+
+void main() {
+    vec3 direction = normalize(v_direction);
+    float up = max(direction.y, 0.0);
+
+    // Below the horizon is hidden by terrain and fog, so it keeps the horizon colour
+    vec3 color = mix(u_horizon_color, u_zenith_color, sqrt(up));
+
+    // Stars on a grid of directions, fading in towards the zenith
+    float star = step(0.998, hash(floor(direction * 300.0)));
+    color += vec3(star * u_stars * smoothstep(0.0, 0.2, up));
+
+    // The sun is orange low on the sky and glows around its disc
+    float sun_height = u_sun_direction.y;
+    vec3 sun_color = mix(vec3(1.0, 0.45, 0.15), vec3(1.0, 0.95, 0.8), smoothstep(0.0, 0.3, sun_height));
+    float glow = pow(max(dot(direction, u_sun_direction), 0.0), 64.0) * 0.5;
+    float sun = disc(direction, u_sun_direction, u_sun_size) + glow;
+    color += sun_color * sun * smoothstep(-0.05, 0.0, sun_height) * step(0.0, direction.y);
+
+    // The moon is opposite the sun, and only shows at night
+    float moon = disc(direction, -u_sun_direction, u_sun_size * 0.8);
+    color = mix(color, vec3(0.8, 0.85, 0.9), moon * u_stars * step(0.0, direction.y));
+
+    f_color = vec4(color, 1.0);
+}
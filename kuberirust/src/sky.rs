@@ -0,0 +1,85 @@
+use wgpu::util::DeviceExt;
+
+use crate::clock::WorldClock;
+
+//Angular radius of the sun and moon, in radians
+const SUN_SIZE: f32 = 0.04;
+
+//Laid out to match the Sky block in sky.frag
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    //Towards the sun, normalized. The moon is opposite it.
+    sun_direction: [f32; 3],
+    //0 to 1
+    stars: f32,
+    zenith_color: [f32; 3],
+    sun_size: f32,
+    horizon_color: [f32; 3],
+    _padding: u32,
+}
+
+impl SkyUniform {
+    fn new(clock: &WorldClock, day_sky: [f32; 3]) -> Self {
+        Self {
+            sun_direction: clock.sun_direction().into(),
+            stars: clock.stars(),
+            zenith_color: clock.zenith_color(day_sky),
+            sun_size: SUN_SIZE,
+            horizon_color: clock.sky_color(day_sky),
+            _padding: 0,
+        }
+    }
+}
+
+//A gradient from the horizon up, with the sun, the moon and stars. Drawn as one triangle covering
+//the screen before the terrain. The horizon has the fog colour, so far away terrain fades into it.
+pub struct Sky {
+    uniform: SkyUniform,
+    buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Sky {
+    pub fn new(device: &wgpu::Device, clock: &WorldClock, day_sky: [f32; 3]) -> Self {
+        let uniform = SkyUniform::new(clock, day_sky);
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sky Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::FRAGMENT,
+                ty: wgpu::BindingType::UniformBuffer {
+                    dynamic: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("sky_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(buffer.slice(..)),
+            }],
+            label: Some("sky_bind_group"),
+        });
+        Self {
+            uniform,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    //Move the sun and recolour the sky for the clock's time of day
+    pub fn update(&mut self, queue: &wgpu::Queue, clock: &WorldClock, day_sky: [f32; 3]) {
+        self.uniform = SkyUniform::new(clock, day_sky);
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+}
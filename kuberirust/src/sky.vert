@@ -0,0 +1,22 @@
+#version 450
+
+// Direction from the camera, world space
+layout(location=0) out vec3 v_direction;
+
+// Same uniform buffer as the terrain, in its own pipeline layout
+layout(set=0, binding=0)
+uniform Uniforms {
+    vec3 u_view_position;
+    mat4 u_view_proj;
+};
+
+void main() {
+    // One triangle covering the screen, no vertex buffer needed
+    vec2 position = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2) * 2.0 - 1.0;
+    gl_Position = vec4(position, 1.0, 1.0);
+
+    // The point on the far plane behind this corner, minus the camera position, so moving
+    // the camera doesn't move the sky
+    vec4 far = inverse(u_view_proj) * vec4(position, 1.0, 1.0);
+    v_direction = far.xyz / far.w - u_view_position;
+}
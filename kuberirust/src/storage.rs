@@ -0,0 +1,377 @@
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::clock::WorldClock;
+use crate::model::{BlockRegistry, BlockType, Chunk, ChunkPos, CHUNKSIZE, DEFAULT_SEED};
+
+//Bump when the layout of region files or world.ron changes
+pub const FORMAT_VERSION: u32 = 1;
+
+const REGION_MAGIC: &[u8; 4] = b"KRRG";
+//Chunks along each axis of a region
+pub const REGION_SIZE: i32 = 8;
+const REGION_VOLUME: usize = (REGION_SIZE * REGION_SIZE * REGION_SIZE) as usize;
+//Magic, version, chunk size, then offset and length of each chunk
+const HEADER_LEN: usize = 12 + REGION_VOLUME * 8;
+const COMPRESSION_LEVEL: u8 = 6;
+
+//Camera placement, so a world resumes where it was left
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SavedCamera {
+    pub position: [f32; 3],
+    //Radians
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+//Contents of world.ron
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorldMeta {
+    pub version: u32,
+    pub seed: u64,
+    pub chunk_size: i32,
+    #[serde(default)]
+    pub camera: Option<SavedCamera>,
+    #[serde(default)]
+    pub clock: Option<WorldClock>,
+}
+
+impl Default for WorldMeta {
+    fn default() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            seed: DEFAULT_SEED,
+            chunk_size: CHUNKSIZE,
+            camera: None,
+            clock: None,
+        }
+    }
+}
+
+//A world on disk: world.ron plus region files of REGION_SIZE^3 chunks each.
+//
+//Region file, all numbers little endian u32:
+//  "KRRG", version, chunk size,
+//  REGION_VOLUME x (offset, length) of each chunk in the file, offset 0 if the chunk isn't saved,
+//  chunk data.
+//Each chunk is zlib compressed: number of block types, their names (u8 length + utf8),
+//then one u16 index into those names per voxel, in Chunk::index order.
+//Blocks are stored by name, so ids may change when blocks.ron changes.
+#[derive(Debug, Clone)]
+pub struct WorldStorage {
+    dir: PathBuf,
+    pub meta: WorldMeta,
+    registry: Arc<BlockRegistry>,
+}
+
+impl WorldStorage {
+    //Opens the world in dir. A missing dir or world.ron is a new world with default settings.
+    pub fn open<P: AsRef<Path>>(dir: P, registry: Arc<BlockRegistry>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        let meta_path = dir.join("world.ron");
+        let meta = if meta_path.exists() {
+            let src = fs::read_to_string(&meta_path)
+                .with_context(|| format!("Unable to read {}", meta_path.display()))?;
+            let meta: WorldMeta = ron::de::from_str(&src)
+                .with_context(|| format!("Invalid world file {}", meta_path.display()))?;
+            if meta.version != FORMAT_VERSION {
+                bail!(
+                    "World {} has format version {}, expected {}",
+                    dir.display(),
+                    meta.version,
+                    FORMAT_VERSION
+                );
+            }
+            if meta.chunk_size <= 0 {
+                bail!("World {} has invalid chunk size {}", dir.display(), meta.chunk_size);
+            }
+            meta
+        } else {
+            WorldMeta::default()
+        };
+        Ok(Self { dir, meta, registry })
+    }
+
+    pub fn save_meta(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Unable to create world directory {}", self.dir.display()))?;
+        let src = ron::ser::to_string_pretty(&self.meta, ron::ser::PrettyConfig::default())?;
+        write_atomic(&self.dir.join("world.ron"), src.as_bytes())
+    }
+
+    //Region holding the chunk, and the chunk's index in the region's offset table
+    fn region_of(pos: ChunkPos) -> ([i32; 3], usize) {
+        let region = [
+            pos.x.div_euclid(REGION_SIZE),
+            pos.y.div_euclid(REGION_SIZE),
+            pos.z.div_euclid(REGION_SIZE),
+        ];
+        let (x, y, z) = (
+            pos.x.rem_euclid(REGION_SIZE),
+            pos.y.rem_euclid(REGION_SIZE),
+            pos.z.rem_euclid(REGION_SIZE),
+        );
+        (region, (x + REGION_SIZE * (y + REGION_SIZE * z)) as usize)
+    }
+
+    fn region_path(&self, region: [i32; 3]) -> PathBuf {
+        self.dir
+            .join(format!("r.{}.{}.{}.region", region[0], region[1], region[2]))
+    }
+
+    //Reads only the header and the one chunk. Ok(None) if the chunk was never saved.
+    pub fn load_chunk(&self, pos: ChunkPos) -> Result<Option<Chunk>> {
+        let (region, index) = Self::region_of(pos);
+        let path = self.region_path(region);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut file = File::open(&path).with_context(|| format!("Unable to open {}", path.display()))?;
+        let (offset, length) = {
+            let mut header = vec![0; HEADER_LEN];
+            file.read_exact(&mut header)
+                .with_context(|| format!("Truncated region file {}", path.display()))?;
+            let table = self
+                .parse_header(&header)
+                .with_context(|| format!("Invalid region file {}", path.display()))?;
+            table[index]
+        };
+        if offset == 0 {
+            return Ok(None);
+        }
+        let mut data = vec![0; length as usize];
+        file.seek(SeekFrom::Start(offset as u64))?;
+        file.read_exact(&mut data)
+            .with_context(|| format!("Truncated region file {}", path.display()))?;
+        let chunk = self
+            .decode_chunk(&data)
+            .with_context(|| format!("Invalid chunk {:?} in {}", pos, path.display()))?;
+        Ok(Some(chunk))
+    }
+
+    //Each touched region file is rewritten once, keeping the chunks that are already in it
+    pub fn save_chunks<'a, I: IntoIterator<Item = (ChunkPos, &'a Chunk)>>(&self, chunks: I) -> Result<()> {
+        let mut regions: HashMap<[i32; 3], Vec<(usize, Vec<u8>)>> = HashMap::new();
+        for (pos, chunk) in chunks {
+            if chunk.size() != self.meta.chunk_size {
+                bail!("Chunk {:?} has size {}, world has {}", pos, chunk.size(), self.meta.chunk_size);
+            }
+            let (region, index) = Self::region_of(pos);
+            let data = self
+                .encode_chunk(chunk)
+                .with_context(|| format!("Unable to save chunk {:?}", pos))?;
+            regions.entry(region).or_default().push((index, data));
+        }
+        if regions.is_empty() {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Unable to create world directory {}", self.dir.display()))?;
+
+        for (region, new_chunks) in regions {
+            let path = self.region_path(region);
+            let mut blobs: Vec<Option<Vec<u8>>> = if path.exists() {
+                let bytes = fs::read(&path).with_context(|| format!("Unable to read {}", path.display()))?;
+                self.split_region(&bytes)
+                    .with_context(|| format!("Invalid region file {}", path.display()))?
+            } else {
+                vec![None; REGION_VOLUME]
+            };
+            for (index, data) in new_chunks {
+                blobs[index] = Some(data);
+            }
+            write_atomic(&path, &self.join_region(&blobs))?;
+        }
+        Ok(())
+    }
+
+    fn parse_header(&self, header: &[u8]) -> Result<Vec<(u32, u32)>> {
+        if &header[0..4] != REGION_MAGIC {
+            bail!("Not a region file");
+        }
+        let version = read_u32(header, 4);
+        if version != FORMAT_VERSION {
+            bail!("Region file has format version {}, expected {}", version, FORMAT_VERSION);
+        }
+        let chunk_size = read_u32(header, 8);
+        if chunk_size as i32 != self.meta.chunk_size {
+            bail!("Region file has chunk size {}, world has {}", chunk_size, self.meta.chunk_size);
+        }
+        Ok((0..REGION_VOLUME)
+            .map(|i| (read_u32(header, 12 + i * 8), read_u32(header, 16 + i * 8)))
+            .collect())
+    }
+
+    //Compressed data of each chunk in a region file
+    fn split_region(&self, bytes: &[u8]) -> Result<Vec<Option<Vec<u8>>>> {
+        if bytes.len() < HEADER_LEN {
+            bail!("Truncated header");
+        }
+        self.parse_header(&bytes[..HEADER_LEN])?
+            .into_iter()
+            .map(|(offset, length)| {
+                if offset == 0 {
+                    return Ok(None);
+                }
+                let (start, end) = (offset as usize, offset as usize + length as usize);
+                if start < HEADER_LEN || end > bytes.len() {
+                    bail!("Chunk data at {}..{} is outside the file", start, end);
+                }
+                Ok(Some(bytes[start..end].to_vec()))
+            })
+            .collect()
+    }
+
+    fn join_region(&self, blobs: &[Option<Vec<u8>>]) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(REGION_MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        header.extend_from_slice(&(self.meta.chunk_size as u32).to_le_bytes());
+        let mut data = Vec::new();
+        for blob in blobs {
+            let (offset, length) = match blob {
+                Some(blob) => {
+                    let offset = HEADER_LEN + data.len();
+                    data.extend_from_slice(blob);
+                    (offset as u32, blob.len() as u32)
+                }
+                None => (0, 0),
+            };
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(&length.to_le_bytes());
+        }
+        header.extend_from_slice(&data);
+        header
+    }
+
+    fn encode_chunk(&self, chunk: &Chunk) -> Result<Vec<u8>> {
+        let mut palette: Vec<BlockType> = Vec::new();
+        let mut indices = Vec::with_capacity(chunk.volume() * 2);
+        for i in 0..chunk.volume() {
+            let blocktype = chunk.get_at(i);
+            let p = match palette.iter().position(|b| *b == blocktype) {
+                Some(p) => p,
+                None => {
+                    palette.push(blocktype);
+                    palette.len() - 1
+                }
+            };
+            indices.extend_from_slice(&(p as u16).to_le_bytes());
+        }
+
+        let mut raw = Vec::with_capacity(indices.len() + 64);
+        raw.extend_from_slice(&(palette.len() as u16).to_le_bytes());
+        for blocktype in palette {
+            let name = self.registry.get(blocktype).name.as_bytes();
+            if name.len() > u8::MAX as usize {
+                bail!("Block name '{}' is too long", self.registry.get(blocktype).name);
+            }
+            raw.push(name.len() as u8);
+            raw.extend_from_slice(name);
+        }
+        raw.extend_from_slice(&indices);
+        Ok(miniz_oxide::deflate::compress_to_vec_zlib(&raw, COMPRESSION_LEVEL))
+    }
+
+    fn decode_chunk(&self, data: &[u8]) -> Result<Chunk> {
+        let size = self.meta.chunk_size;
+        let raw = miniz_oxide::inflate::decompress_to_vec_zlib(data)
+            .map_err(|e| anyhow!("Unable to decompress chunk: {:?}", e))?;
+        let truncated = || anyhow!("Truncated chunk data");
+
+        let palette_len = u16::from_le_bytes([*raw.first().ok_or_else(truncated)?, *raw.get(1).ok_or_else(truncated)?]);
+        let mut at = 2;
+        let mut palette = Vec::with_capacity(palette_len as usize);
+        for _ in 0..palette_len {
+            let len = *raw.get(at).ok_or_else(truncated)? as usize;
+            let name = raw.get(at + 1..at + 1 + len).ok_or_else(truncated)?;
+            let name = std::str::from_utf8(name)?;
+            palette.push(
+                self.registry
+                    .by_name(name)
+                    .with_context(|| format!("Saved block '{}' is not in the registry", name))?,
+            );
+            at += 1 + len;
+        }
+
+        let mut chunk = Chunk::new(size);
+        let indices = raw.get(at..).ok_or_else(truncated)?;
+        if indices.len() != chunk.volume() * 2 {
+            bail!("Chunk has {} bytes of blocks, expected {}", indices.len(), chunk.volume() * 2);
+        }
+        for (i, pair) in indices.chunks(2).enumerate() {
+            let p = u16::from_le_bytes([pair[0], pair[1]]) as usize;
+            let blocktype = *palette
+                .get(p)
+                .with_context(|| format!("Block index {} outside palette of {}", p, palette.len()))?;
+            chunk.set(chunk.local_from_index(i), blocktype);
+        }
+        chunk.compress();
+        Ok(chunk)
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+//Write to a temporary file first, so a crash never leaves a half written file behind
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data).with_context(|| format!("Unable to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("Unable to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //An empty directory of its own for each test
+    fn temp_world(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("kuberirust-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn chunk(registry: &BlockRegistry, blocks: &[([i32; 3], &str)]) -> Chunk {
+        let mut chunk = Chunk::new(CHUNKSIZE);
+        for (local, name) in blocks {
+            chunk.set(*local, registry.id(name).unwrap());
+        }
+        chunk
+    }
+
+    #[test]
+    fn chunks_round_trip() {
+        let dir = temp_world("round-trip");
+        let registry = Arc::new(BlockRegistry::builtin().unwrap());
+        let storage = WorldStorage::open(&dir, registry.clone()).unwrap();
+        let a = chunk(&registry, &[([0, 0, 0], "stone"), ([15, 15, 15], "grass")]);
+        let mut b = chunk(&registry, &[([1, 2, 3], "lamp"), ([4, 5, 6], "dirt")]);
+        b.compress();
+        //Both in region 0,0,0, saved one at a time
+        let (pos_a, pos_b) = (ChunkPos::new(0, 0, 0), ChunkPos::new(3, 0, 7));
+        assert_eq!(WorldStorage::region_of(pos_a).0, WorldStorage::region_of(pos_b).0);
+        storage.save_chunks(std::iter::once((pos_a, &a))).unwrap();
+        storage.save_chunks(std::iter::once((pos_b, &b))).unwrap();
+        storage.save_meta().unwrap();
+
+        let reopened = WorldStorage::open(&dir, registry).unwrap();
+        let loaded_a = reopened.load_chunk(pos_a).unwrap().unwrap();
+        let loaded_b = reopened.load_chunk(pos_b).unwrap().unwrap();
+        for i in 0..a.volume() {
+            assert_eq!(loaded_a.get_at(i), a.get_at(i));
+            assert_eq!(loaded_b.get_at(i), b.get_at(i));
+        }
+        //Never saved, in the same region and in one without a file
+        assert_eq!(reopened.load_chunk(ChunkPos::new(1, 0, 0)).unwrap(), None);
+        assert_eq!(reopened.load_chunk(ChunkPos::new(-1, 0, 0)).unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashSet;
+
+use crate::model::ChunkPos;
+
+//In chunks. Keep view_radius * chunk size below the far plane of the projection.
+pub const DEFAULT_VIEW_RADIUS: i32 = 5;
+pub const DEFAULT_VERTICAL_RADIUS: i32 = 2;
+
+//Decides which chunks should be loaded around the camera. Model::update_chunks hands the work to the chunk workers.
+#[derive(Debug, Clone)]
+pub struct ChunkManager {
+    //In chunks. Horizontal range is a circle, vertical range a few layers above and below.
+    pub view_radius: i32,
+    pub vertical_radius: i32,
+    //Bytes of finished meshes copied to the GPU per frame, so moving around doesn't make frames spike
+    pub upload_bytes_per_frame: usize,
+    center: Option<ChunkPos>,
+    //Chunks in range of center, nearest first
+    wanted: Vec<ChunkPos>,
+    wanted_set: HashSet<ChunkPos>,
+}
+
+impl ChunkManager {
+    pub fn new(view_radius: i32, vertical_radius: i32) -> Self {
+        Self {
+            view_radius,
+            vertical_radius,
+            upload_bytes_per_frame: 1 << 20,
+            center: None,
+            wanted: Vec::new(),
+            wanted_set: HashSet::new(),
+        }
+    }
+
+    //Move the range to the chunk the camera is in. Returns true if it moved.
+    pub fn set_center(&mut self, center: ChunkPos) -> bool {
+        if self.center == Some(center) {
+            return false;
+        }
+        self.center = Some(center);
+        let r = self.view_radius;
+        let vr = self.vertical_radius;
+        self.wanted.clear();
+        for dx in -r..=r {
+            for dz in -r..=r {
+                if dx * dx + dz * dz > r * r {
+                    continue;
+                }
+                for dy in -vr..=vr {
+                    self.wanted.push(center.offset(dx, dy, dz));
+                }
+            }
+        }
+        self.wanted.sort_by_key(|p| Self::distance2(center, *p));
+        self.wanted_set = self.wanted.iter().cloned().collect();
+        true
+    }
+
+    //Squared distance in chunks
+    pub fn distance2(a: ChunkPos, b: ChunkPos) -> i32 {
+        let (dx, dy, dz) = (a.x - b.x, a.y - b.y, a.z - b.z);
+        dx * dx + dy * dy + dz * dz
+    }
+
+    pub fn in_range(&self, pos: ChunkPos) -> bool {
+        self.wanted_set.contains(&pos)
+    }
+
+    //All chunks in range, nearest first
+    pub fn wanted(&self) -> &[ChunkPos] {
+        &self.wanted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_is_a_cylinder_around_the_center() {
+        let mut manager = ChunkManager::new(2, 1);
+        let center = ChunkPos::new(3, 0, -4);
+        assert!(!manager.in_range(center));
+        assert!(manager.set_center(center));
+        assert!(!manager.set_center(center));
+
+        assert!(manager.in_range(center));
+        assert!(manager.in_range(center.offset(2, 1, 0)));
+        assert!(manager.in_range(center.offset(1, -1, 1)));
+        assert!(!manager.in_range(center.offset(0, 2, 0)));
+        assert!(!manager.in_range(center.offset(2, 0, 1)));
+        assert!(!manager.in_range(center.offset(3, 0, 0)));
+        //13 columns within radius 2, three layers each
+        assert_eq!(manager.wanted().len(), 13 * 3);
+    }
+
+    #[test]
+    fn wanted_chunks_are_nearest_first() {
+        let mut manager = ChunkManager::new(3, 2);
+        let center = ChunkPos::new(-1, 2, 5);
+        manager.set_center(center);
+        let wanted = manager.wanted();
+        assert_eq!(wanted[0], center);
+        for pair in wanted.windows(2) {
+            assert!(ChunkManager::distance2(center, pair[0]) <= ChunkManager::distance2(center, pair[1]));
+        }
+    }
+
+    #[test]
+    fn moving_the_center_drops_the_far_side() {
+        let mut manager = ChunkManager::new(2, 0);
+        manager.set_center(ChunkPos::new(0, 0, 0));
+        let before: Vec<ChunkPos> = manager.wanted().to_vec();
+        manager.set_center(ChunkPos::new(1, 0, 0));
+        let mut unloaded: Vec<ChunkPos> = before.into_iter().filter(|pos| !manager.in_range(*pos)).collect();
+        unloaded.sort_by_key(|pos| pos.z);
+        assert_eq!(
+            unloaded,
+            vec![
+                ChunkPos::new(0, 0, -2),
+                ChunkPos::new(-1, 0, -1),
+                ChunkPos::new(-2, 0, 0),
+                ChunkPos::new(-1, 0, 1),
+                ChunkPos::new(0, 0, 2),
+            ]
+        );
+    }
+}
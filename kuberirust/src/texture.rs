@@ -3,6 +3,8 @@ use image::GenericImageView;
 
 #[derive(Debug)]
 pub struct Texture {
+    //Not read, but kept alive for the view
+    #[allow(dead_code)]
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
@@ -15,10 +17,21 @@ impl Texture {
         device: &wgpu::Device,
         sc_desc: &wgpu::SwapChainDescriptor,
         label: &str,
+    ) -> Self {
+        Self::create_depth_texture_with_size(device, sc_desc.width, sc_desc.height, label)
+    }
+
+    //A depth texture that isn't the size of the screen, like a shadow map.
+    //The sampler compares, so shaders read it with sampler2DShadow.
+    pub fn create_depth_texture_with_size(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
-            width: sc_desc.width,
-            height: sc_desc.height,
+            width,
+            height,
             depth: 1,
         };
         let desc = wgpu::TextureDescriptor {
@@ -69,7 +82,7 @@ impl Texture {
         label: Option<&str>,
     ) -> Result<Self> {
         //let rgba = img.as_rgba8().unwrap();
-        let rgba = img.to_rgba();
+        let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
 
         let size = wgpu::Extent3d {
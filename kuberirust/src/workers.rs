@@ -0,0 +1,247 @@
+use anyhow::{anyhow, Result};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+
+use crate::atlas::TextureAtlas;
+use crate::mesher::{self, MeshData, MeshingMode};
+use crate::model::{Chunk, ChunkPos, World};
+use crate::storage::WorldStorage;
+use crate::streaming::ChunkManager;
+use crate::worldgen::WorldGenerator;
+
+//Work that can be done off the main thread
+pub enum Job {
+    //Read the chunk from disk, or generate it if it was never saved
+    Load(ChunkPos),
+    //Build the mesh of a chunk. The world holds copies of the chunk and its neighbours.
+    Mesh(ChunkPos, World, MeshingMode),
+}
+
+impl Job {
+    pub fn pos(&self) -> ChunkPos {
+        match self {
+            Job::Load(pos) => *pos,
+            Job::Mesh(pos, _, _) => *pos,
+        }
+    }
+}
+
+//A job that panicked gives an error, so whoever waits for it isn't left hanging
+pub enum JobResult {
+    Loaded(ChunkPos, Result<Chunk>),
+    Meshed(ChunkPos, Result<MeshData>),
+}
+
+//What workers need to load chunks and build meshes
+#[derive(Debug, Clone)]
+pub struct ChunkSource {
+    pub generator: Arc<dyn WorldGenerator>,
+    pub storage: Option<WorldStorage>,
+    pub atlas: Arc<TextureAtlas>,
+    pub chunk_size: i32,
+}
+
+impl ChunkSource {
+    fn run(&self, job: Job) -> JobResult {
+        match job {
+            Job::Load(pos) => JobResult::Loaded(pos, catch_panic(|| self.load(pos)).and_then(|chunk| chunk)),
+            Job::Mesh(pos, world, mode) => {
+                JobResult::Meshed(pos, catch_panic(|| mesher::build_mesh(&world, &self.atlas, pos, mode)))
+            }
+        }
+    }
+
+    //Saved chunk if there is one, otherwise a freshly generated one
+    pub fn load(&self, pos: ChunkPos) -> Result<Chunk> {
+        if let Some(storage) = &self.storage {
+            if let Some(chunk) = storage.load_chunk(pos)? {
+                return Ok(chunk);
+            }
+        }
+        Ok(self.generator.generate_chunk(pos, self.chunk_size))
+    }
+}
+
+//Turns a panic into an error, so the worker keeps going
+fn catch_panic<T, F: FnOnce() -> T>(f: F) -> Result<T> {
+    catch_unwind(AssertUnwindSafe(f)).map_err(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        anyhow!("Chunk worker panicked: {}", message)
+    })
+}
+
+struct Queue {
+    jobs: Vec<Job>,
+    //Jobs nearest to this are taken first
+    center: ChunkPos,
+    shutdown: bool,
+}
+
+//Threads taking jobs from a shared queue, nearest chunk first. Results are picked up with try_recv.
+pub struct WorkerPool {
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+    results: Receiver<JobResult>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for WorkerPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WorkerPool")
+            .field("threads", &self.threads.len())
+            .field("queued", &self.queued())
+            .finish()
+    }
+}
+
+impl WorkerPool {
+    //One thread per core, leaving one for the main thread
+    pub fn new(source: ChunkSource) -> Self {
+        let cores = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(2);
+        Self::with_threads(source, cores.max(2) - 1)
+    }
+
+    pub fn with_threads(source: ChunkSource, threads: usize) -> Self {
+        let queue = Arc::new((
+            Mutex::new(Queue {
+                jobs: Vec::new(),
+                center: ChunkPos::default(),
+                shutdown: false,
+            }),
+            Condvar::new(),
+        ));
+        let (sender, results) = channel();
+        let source = Arc::new(source);
+        let threads = (0..threads.max(1))
+            .map(|i| {
+                let queue = queue.clone();
+                let sender = sender.clone();
+                let source = source.clone();
+                std::thread::Builder::new()
+                    .name(format!("chunk worker {}", i))
+                    .spawn(move || work(&queue, &sender, &source))
+                    .expect("Unable to start chunk worker thread")
+            })
+            .collect();
+        Self { queue, results, threads }
+    }
+
+    pub fn submit(&self, job: Job) {
+        let (lock, wakeup) = &*self.queue;
+        lock.lock().unwrap().jobs.push(job);
+        wakeup.notify_one();
+    }
+
+    //Prioritize jobs near center, and drop queued jobs for chunks that are no longer wanted.
+    //Returns the dropped jobs. Jobs already running finish, their results can be thrown away.
+    pub fn set_center<F: Fn(ChunkPos) -> bool>(&self, center: ChunkPos, wanted: F) -> Vec<Job> {
+        let (lock, _) = &*self.queue;
+        let mut queue = lock.lock().unwrap();
+        queue.center = center;
+        let (keep, cancelled) = queue.jobs.drain(..).partition(|job| wanted(job.pos()));
+        queue.jobs = keep;
+        cancelled
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queue.0.lock().unwrap().jobs.len()
+    }
+
+    //A finished job, if there is one
+    pub fn try_recv(&self) -> Option<JobResult> {
+        match self.results.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => panic!("Chunk workers stopped"),
+        }
+    }
+
+    //Block until a job is finished
+    pub fn recv(&self) -> JobResult {
+        self.results.recv().expect("Chunk workers stopped")
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        {
+            let (lock, wakeup) = &*self.queue;
+            let mut queue = lock.lock().unwrap();
+            queue.shutdown = true;
+            queue.jobs.clear();
+            wakeup.notify_all();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn work(queue: &(Mutex<Queue>, Condvar), results: &Sender<JobResult>, source: &ChunkSource) {
+    let (lock, wakeup) = queue;
+    loop {
+        let job = {
+            let mut queue = lock.lock().unwrap();
+            while queue.jobs.is_empty() && !queue.shutdown {
+                queue = wakeup.wait(queue).unwrap();
+            }
+            if queue.shutdown {
+                return;
+            }
+            let center = queue.center;
+            let nearest = (0..queue.jobs.len())
+                .min_by_key(|i| ChunkManager::distance2(center, queue.jobs[*i].pos()))
+                .unwrap();
+            queue.jobs.swap_remove(nearest)
+        };
+        if results.send(source.run(job)).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::BlockRegistry;
+    use crate::worldgen::TerrainGenerator;
+
+    //Panics for chunks above y = 0
+    #[derive(Debug)]
+    struct BrokenGenerator(TerrainGenerator);
+
+    impl WorldGenerator for BrokenGenerator {
+        fn generate_chunk(&self, pos: ChunkPos, chunk_size: i32) -> Chunk {
+            assert!(pos.y <= 0, "no chunks up here");
+            self.0.generate_chunk(pos, chunk_size)
+        }
+    }
+
+    #[test]
+    fn panicking_job_gives_an_error() {
+        let registry = BlockRegistry::builtin().unwrap();
+        let source = ChunkSource {
+            generator: Arc::new(BrokenGenerator(TerrainGenerator::new(1, &registry).unwrap())),
+            storage: None,
+            atlas: Arc::new(TextureAtlas::builtin(&registry).unwrap()),
+            chunk_size: 16,
+        };
+        let workers = WorkerPool::with_threads(source, 1);
+        workers.submit(Job::Load(ChunkPos::new(0, 1, 0)));
+        match workers.recv() {
+            JobResult::Loaded(_, chunk) => assert!(chunk.is_err()),
+            JobResult::Meshed(..) => panic!("expected a loaded chunk"),
+        }
+        //The worker is still there
+        workers.submit(Job::Load(ChunkPos::new(0, 0, 0)));
+        match workers.recv() {
+            JobResult::Loaded(_, chunk) => assert!(chunk.is_ok()),
+            JobResult::Meshed(..) => panic!("expected a loaded chunk"),
+        }
+    }
+}
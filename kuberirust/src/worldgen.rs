@@ -0,0 +1,212 @@
+use anyhow::*;
+
+use crate::model::{BlockRegistry, BlockType, Chunk, ChunkPos};
+
+//Anything that can fill a chunk. Must be deterministic: same position, same chunk.
+//Runs on the chunk worker threads.
+pub trait WorldGenerator: std::fmt::Debug + Send + Sync {
+    fn generate_chunk(&self, pos: ChunkPos, chunk_size: i32) -> Chunk;
+}
+
+//Heightmap terrain: stone at the bottom, a few layers of dirt and grass on top.
+#[derive(Debug, Clone)]
+pub struct TerrainGenerator {
+    pub seed: u64,
+    //Terrain height is base_height + noise * height_range
+    pub base_height: i32,
+    pub height_range: f32,
+    //Horizontal size of the largest hills in blocks
+    pub scale: f32,
+    pub octaves: u32,
+    pub dirt_depth: i32,
+    grass: BlockType,
+    dirt: BlockType,
+    stone: BlockType,
+}
+
+impl TerrainGenerator {
+    //Needs "grass", "dirt" and "stone" in the registry
+    pub fn new(seed: u64, registry: &BlockRegistry) -> Result<Self> {
+        Ok(Self {
+            seed,
+            base_height: 4,
+            height_range: 10.0,
+            scale: 32.0,
+            octaves: 4,
+            dirt_depth: 3,
+            grass: registry.id("grass")?,
+            dirt: registry.id("dirt")?,
+            stone: registry.id("stone")?,
+        })
+    }
+
+    //y of the topmost (grass) block in column x,z
+    pub fn height_at(&self, x: i32, z: i32) -> i32 {
+        let n = fbm(self.seed, x as f32 / self.scale, z as f32 / self.scale, self.octaves);
+        self.base_height + (n * self.height_range).floor() as i32
+    }
+
+    //Dirt layer thickness varies a little, driven by its own noise
+    fn dirt_depth_at(&self, x: i32, z: i32) -> i32 {
+        let n = value_noise(self.seed ^ 0x5bd1_e995, x as f32 / 8.0, z as f32 / 8.0);
+        self.dirt_depth + (n * 2.0).floor() as i32
+    }
+
+    fn layer(&self, y: i32, height: i32, dirt_depth: i32) -> BlockType {
+        if y > height {
+            BlockType::AIR
+        } else if y == height {
+            self.grass
+        } else if y > height - dirt_depth {
+            self.dirt
+        } else {
+            self.stone
+        }
+    }
+}
+
+impl WorldGenerator for TerrainGenerator {
+    fn generate_chunk(&self, pos: ChunkPos, chunk_size: i32) -> Chunk {
+        let mut chunk = Chunk::new(chunk_size);
+        let origin = pos.origin(chunk_size);
+        for x in 0..chunk_size {
+            for z in 0..chunk_size {
+                let wx = origin.x + x;
+                let wz = origin.z + z;
+                let height = self.height_at(wx, wz);
+                if height < origin.y {
+                    continue;
+                }
+                let dirt_depth = self.dirt_depth_at(wx, wz);
+                for y in 0..chunk_size.min(height - origin.y + 1) {
+                    chunk.set([x, y, z], self.layer(origin.y + y, height, dirt_depth));
+                }
+            }
+        }
+        chunk.compress();
+        chunk
+    }
+}
+
+fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+//Random value in 0..1 for a lattice point
+fn lattice(seed: u64, x: i32, z: i32) -> f32 {
+    let h = splitmix64(seed ^ splitmix64((x as u32 as u64) | ((z as u32 as u64) << 32)));
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+//Smoothly interpolated lattice values, in 0..1
+pub fn value_noise(seed: u64, x: f32, z: f32) -> f32 {
+    let x0 = x.floor();
+    let z0 = z.floor();
+    let (ix, iz) = (x0 as i32, z0 as i32);
+    let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+    let tx = smooth(x - x0);
+    let tz = smooth(z - z0);
+    let a = lattice(seed, ix, iz);
+    let b = lattice(seed, ix + 1, iz);
+    let c = lattice(seed, ix, iz + 1);
+    let d = lattice(seed, ix + 1, iz + 1);
+    let ab = a + (b - a) * tx;
+    let cd = c + (d - c) * tx;
+    ab + (cd - ab) * tz
+}
+
+//Several octaves of value noise, each twice the frequency and half the amplitude. In 0..1.
+pub fn fbm(seed: u64, x: f32, z: f32, octaves: u32) -> f32 {
+    let mut sum = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut total = 0.0;
+    for octave in 0..octaves {
+        sum += value_noise(seed.wrapping_add(octave as u64), x * frequency, z * frequency) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+    sum / total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: i32 = 16;
+
+    fn generator(seed: u64) -> TerrainGenerator {
+        TerrainGenerator::new(seed, &BlockRegistry::builtin().unwrap()).unwrap()
+    }
+
+    fn block_at(generator: &TerrainGenerator, x: i32, y: i32, z: i32) -> BlockType {
+        generator.layer(y, generator.height_at(x, z), generator.dirt_depth_at(x, z))
+    }
+
+    #[test]
+    fn same_seed_same_chunk() {
+        let pos = ChunkPos::new(3, 0, -2);
+        assert_eq!(
+            generator(7).generate_chunk(pos, SIZE),
+            generator(7).generate_chunk(pos, SIZE)
+        );
+        assert_ne!(
+            generator(7).generate_chunk(pos, SIZE),
+            generator(8).generate_chunk(pos, SIZE)
+        );
+    }
+
+    #[test]
+    fn heights_stay_in_range() {
+        let generator = generator(1);
+        let max = generator.base_height + generator.height_range as i32;
+        for x in -100..100 {
+            for z in -100..100 {
+                let height = generator.height_at(x, z);
+                assert!(height >= generator.base_height && height <= max, "{} at {},{}", height, x, z);
+            }
+        }
+    }
+
+    //Air above grass, then dirt, then stone all the way down
+    #[test]
+    fn columns_are_layered() {
+        let generator = generator(2);
+        let registry = BlockRegistry::builtin().unwrap();
+        let (grass, dirt, stone) = (
+            registry.id("grass").unwrap(),
+            registry.id("dirt").unwrap(),
+            registry.id("stone").unwrap(),
+        );
+        for x in -20..20 {
+            for z in -20..20 {
+                let height = generator.height_at(x, z);
+                let column: Vec<BlockType> = (height - 10..=height + 2)
+                    .rev()
+                    .map(|y| block_at(&generator, x, y, z))
+                    .collect();
+                assert_eq!(&column[..3], &[BlockType::AIR, BlockType::AIR, grass]);
+                //The grass block counts towards the dirt depth
+                let dirt_depth = generator.dirt_depth_at(x, z) as usize - 1;
+                assert!(column[3..3 + dirt_depth].iter().all(|b| *b == dirt), "at {},{}", x, z);
+                assert!(column[3 + dirt_depth..].iter().all(|b| *b == stone), "at {},{}", x, z);
+            }
+        }
+    }
+
+    #[test]
+    fn chunks_match_block_at() {
+        let generator = generator(3);
+        for pos in [ChunkPos::new(0, 0, 0), ChunkPos::new(-1, 0, 2), ChunkPos::new(1, -1, -1)].iter() {
+            let chunk = generator.generate_chunk(*pos, SIZE);
+            for (local, blocktype) in chunk.iter() {
+                let p = pos.to_world(local, SIZE);
+                assert_eq!(blocktype, block_at(&generator, p.x, p.y, p.z), "at {:?}", p);
+            }
+        }
+    }
+}